@@ -0,0 +1,46 @@
+#!/usr/bin/env bash
+set -x
+set -eo pipefail
+
+# Check if Couchbase CLI is installed
+if ! [ -x "$(command -v couchbase-cli)" ]; then
+    echo >&2 "Error: couchbase-cli is not installed."
+    exit 1
+fi
+
+# Define environment variables
+CB_HOST="localhost"
+CB_PORT=8091
+CB_USERNAME="Administrator"
+CB_PASSWORD="password"
+
+# Function to wait for Couchbase to be ready
+wait_for_couchbase() {
+    echo "Waiting for Couchbase to start..."
+    for i in {1..30}; do  # Retry for 30 times with 5 seconds interval
+        if curl -sf http://localhost:8091/ui/index.html &> /dev/null; then
+            echo "Couchbase Web UI is up and running."
+            return 0
+        fi
+        echo "Waiting for Couchbase to start (attempt: $i)..."
+        sleep 5
+    done
+    echo "Couchbase did not start in time."
+    exit 1
+}
+
+# Wait for Couchbase to be ready
+wait_for_couchbase
+
+# Initialize a new cluster, buckets, scopes, collections and indexes are provisioned by the services
+if ! couchbase-cli server-list -c ${CB_HOST}:${CB_PORT} -u ${CB_USERNAME} -p ${CB_PASSWORD} &> /dev/null; then
+    couchbase-cli cluster-init -c ${CB_HOST}:${CB_PORT} \
+        --cluster-username ${CB_USERNAME} \
+        --cluster-password ${CB_PASSWORD} \
+        --services data,index,query \
+        --cluster-ramsize 1024 \
+        --cluster-index-ramsize 256 \
+        --cluster-fts-ramsize 256 \
+        --cluster-eventing-ramsize 256 \
+        --cluster-analytics-ramsize 1024
+fi
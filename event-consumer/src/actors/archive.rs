@@ -0,0 +1,170 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use couchbase::{Cluster, QueryOptions};
+use flate2::{write::GzEncoder, Compression};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use tokio::time::interval;
+use transactions_store::layout::CollectionLayout;
+
+use crate::{
+    configuration::{ArchiveSettings, DatabaseSettings},
+    model::TransactionType,
+};
+
+// Expiry (seconds since epoch) up to which the documents of a collection were archived,
+// kept next to its archive files so it survives restarts
+const WATERMARK_FILE: &str = "watermark";
+
+pub struct ArchiveActor {
+    pub cluster: Arc<Cluster>,
+    pub bucket_name: String,
+    pub scope_name: String,
+    pub layout: CollectionLayout,
+    pub settings: ArchiveSettings,
+}
+
+impl ArchiveActor {
+    pub fn new(
+        cluster: Arc<Cluster>,
+        database: &DatabaseSettings,
+        settings: ArchiveSettings,
+    ) -> ArchiveActor {
+        ArchiveActor {
+            cluster,
+            bucket_name: database.bucket_name.clone(),
+            scope_name: database.scope_name.clone(),
+            layout: database.layout.clone(),
+            settings,
+        }
+    }
+
+    // Archives every document expiring from the watermark of its collection up to the lead
+    // time ahead of now. Late events get an expiry of at least that far ahead (see
+    // `RetentionSettings::expiry`), so they land in a later window instead of below the watermark.
+    async fn archive_expiring(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let to = now + self.settings.lead_time_days * 24 * 60 * 60;
+
+        let type_names = TransactionType::ALL
+            .iter()
+            .map(|transaction_type| transaction_type.to_string())
+            .collect::<Vec<_>>();
+        let type_names = type_names.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let partitions = self
+            .layout
+            .partitions(
+                &self.cluster,
+                &self.bucket_name,
+                &self.scope_name,
+                &type_names,
+            )
+            .await?;
+
+        for partition in partitions {
+            // A shortened lead time moves the window back rather than skipping documents
+            let from = self.read_watermark(&partition.collection_name).min(to);
+
+            let predicate = partition
+                .predicate
+                .map(|predicate| format!(" AND {}", predicate))
+                .unwrap_or_default();
+            let query = format!(
+                "SELECT META(t).id AS id, META(t).expiration AS expiration, t AS document \
+                FROM `{}`.`{}`.`{}` AS t \
+                WHERE META(t).expiration > 0 \
+                AND META(t).expiration >= $from AND META(t).expiration < $to{}",
+                self.bucket_name, self.scope_name, partition.collection_name, predicate
+            );
+            let options =
+                QueryOptions::default().named_parameters(json!({ "from": from, "to": to }));
+
+            let mut result = self.cluster.query(query, options).await?;
+            let mut rows = result.rows::<Value>();
+
+            let mut lines = vec![];
+            while let Some(row) = rows.next().await {
+                lines.push(row?.to_string());
+            }
+
+            if !lines.is_empty() {
+                let path = self
+                    .collection_directory(&partition.collection_name)
+                    .join(format!("{}-{}.ndjson.gz", from, to));
+                let count = lines.len();
+
+                tokio::task::spawn_blocking(move || write_archive(path, lines)).await??;
+
+                tracing::info!(
+                    count,
+                    collection = %partition.collection_name,
+                    expiring_before = to,
+                    "Archived expiring documents"
+                );
+            }
+
+            // Advanced per collection, a failure in a later collection does not archive
+            // this one again
+            self.write_watermark(&partition.collection_name, to)?;
+        }
+
+        Ok(())
+    }
+
+    fn collection_directory(&self, collection_name: &str) -> PathBuf {
+        PathBuf::from(&self.settings.directory).join(collection_name)
+    }
+
+    fn read_watermark(&self, collection_name: &str) -> u64 {
+        fs::read_to_string(
+            self.collection_directory(collection_name)
+                .join(WATERMARK_FILE),
+        )
+        .ok()
+        .and_then(|watermark| watermark.trim().parse().ok())
+        .unwrap_or(0)
+    }
+
+    fn write_watermark(&self, collection_name: &str, watermark: u64) -> std::io::Result<()> {
+        let directory = self.collection_directory(collection_name);
+        fs::create_dir_all(&directory)?;
+        fs::write(directory.join(WATERMARK_FILE), watermark.to_string())
+    }
+
+    pub async fn run(self) {
+        tracing::info!("Archive actor is running");
+
+        let mut interval_timer = interval(Duration::from_secs(self.settings.interval_secs));
+        loop {
+            interval_timer.tick().await;
+
+            if let Err(e) = self.archive_expiring().await {
+                tracing::error!("Error archiving expiring transactions: {}", e);
+            }
+        }
+    }
+}
+
+// Written to a temporary file first, a failed write never leaves a partial archive behind
+fn write_archive(path: PathBuf, lines: Vec<String>) -> std::io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+
+    let partial = path.with_extension("gz.partial");
+    let mut encoder = GzEncoder::new(File::create(&partial)?, Compression::default());
+    for line in lines {
+        encoder.write_all(line.as_bytes())?;
+        encoder.write_all(b"\n")?;
+    }
+    encoder.finish()?.sync_all()?;
+
+    fs::rename(partial, path)
+}
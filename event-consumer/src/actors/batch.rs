@@ -1,51 +1,182 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
 use couchbase::{Cluster, QueryOptions};
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::{Receiver, UnboundedSender};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use transactions_store::{layout::CollectionLayout, provisioning::provision_collection};
 
-use crate::actors::messages::BatchMessage;
+use crate::{
+    actors::messages::BatchMessage,
+    configuration::{DatabaseSettings, RetentionSettings},
+    metrics::Metrics,
+    model::Transaction,
+    offsets::MessagePosition,
+};
 
 pub struct BatchActor {
-    pub cluster: Cluster,
+    pub cluster: Arc<Cluster>,
     pub receiver: Receiver<BatchMessage>,
+    // Positions of the messages of every written batch, committed by the consumer loop
+    pub written: UnboundedSender<Vec<MessagePosition>>,
+    pub bucket_name: String,
+    pub scope_name: String,
+    pub layout: CollectionLayout,
+    pub retention: RetentionSettings,
+    pub provisioned: HashSet<String>,
+    pub metrics: Arc<Metrics>,
 }
 
 impl BatchActor {
-    pub fn new(receiver: Receiver<BatchMessage>) -> BatchActor {
-        let cluster = Cluster::connect("couchbase://127.0.0.1:8091", "Administrator", "password");
+    pub fn new(
+        cluster: Arc<Cluster>,
+        settings: &DatabaseSettings,
+        retention: RetentionSettings,
+        receiver: Receiver<BatchMessage>,
+        written: UnboundedSender<Vec<MessagePosition>>,
+        metrics: Arc<Metrics>,
+    ) -> BatchActor {
+        BatchActor {
+            cluster,
+            receiver,
+            written,
+            bucket_name: settings.bucket_name.clone(),
+            scope_name: settings.scope_name.clone(),
+            layout: settings.layout.clone(),
+            retention,
+            provisioned: HashSet::new(),
+            metrics,
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Writing batch",
+        skip_all,
+        fields(transaction_type = %message.data_type, batch_size = message.batch_data.len())
+    )]
+    async fn handle_message(&mut self, message: BatchMessage) -> Result<(), String> {
+        // Every message span of the batch stays reachable from the write
+        let span = tracing::Span::current();
+        for link in message.links {
+            span.add_link(link);
+        }
+
+        // A batch holds a single type but may still span several collections, e.g. months
+        let type_name = message.data_type.to_string();
+
+        let mut collections: HashMap<String, Vec<Transaction>> = HashMap::new();
+        for transaction in message.batch_data {
+            let collection_name = self
+                .layout
+                .collection_for(&type_name, transaction.timestamp);
+            collections
+                .entry(collection_name)
+                .or_default()
+                .push(transaction);
+        }
+
+        for (collection_name, transactions) in collections {
+            self.provision_collection(&collection_name).await;
+            self.insert(&collection_name, &type_name, transactions)
+                .await?;
+        }
 
-        BatchActor { cluster, receiver }
+        // The consumer loop is gone when shutting down, the batch is consumed again on restart
+        let _ = self.written.send(message.positions);
+
+        Ok(())
     }
 
-    async fn handle_message(&mut self, message: BatchMessage) {
-        println!("Batch actor received a message");
+    async fn provision_collection(&mut self, collection_name: &str) {
+        if self.provisioned.contains(collection_name) {
+            return;
+        }
+
+        match provision_collection(
+            &self.cluster,
+            &self.bucket_name,
+            &self.scope_name,
+            collection_name,
+        )
+        .await
+        {
+            Ok(()) => {
+                self.provisioned.insert(collection_name.to_string());
+            }
+            Err(e) => tracing::error!("Error provisioning collection {}: {}", collection_name, e),
+        }
+    }
 
-        let bucket_name = "transactions";
-        let scope_name = "transactions";
-        let collection_name = message.data_type;
+    #[tracing::instrument(
+        name = "Inserting transactions",
+        skip(self, type_name, transactions),
+        fields(count = transactions.len())
+    )]
+    async fn insert(
+        &self,
+        collection_name: &str,
+        type_name: &str,
+        transactions: Vec<Transaction>,
+    ) -> Result<(), String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time is before unix epoch")
+            .as_secs();
 
         let mut values = Vec::new();
-        for transaction in message.batch_data {
+        for transaction in transactions {
             let key = format!("\"{}\"", transaction.id);
             let value = serde_json::to_string(&transaction).unwrap();
-            values.push(format!("({}, {})", key, value));
+            // Expirations longer than 30 days are only accepted as absolute unix time
+            let expiration =
+                self.retention
+                    .expiry(type_name, transaction.timestamp.unwrap_or_default(), now);
+            values.push(format!(
+                "({}, {}, {{\"expiration\": {}}})",
+                key, value, expiration
+            ));
         }
 
         let values_str = values.join(", ");
 
+        // Batches are consumed again after a failure, transactions already written are replaced
         let query = format!(
-            "INSERT INTO `{}`.`{}`.`{}` (KEY, VALUE) VALUES {}",
-            bucket_name, scope_name, collection_name, values_str
+            "UPSERT INTO `{}`.`{}`.`{}` (KEY, VALUE, OPTIONS) VALUES {}",
+            self.bucket_name, self.scope_name, collection_name, values_str
         );
 
-        self.cluster
-            .query(query, QueryOptions::default())
-            .await
-            .expect("INSERT query failed");
+        let start = Instant::now();
+        let result = self.cluster.query(query, QueryOptions::default()).await;
+        self.metrics
+            .write_duration
+            .with_label_values(&[collection_name])
+            .observe(start.elapsed().as_secs_f64());
+
+        if let Err(e) = result {
+            self.metrics
+                .write_failures
+                .with_label_values(&[collection_name])
+                .inc();
+            return Err(format!(
+                "UPSERT query into {} failed: {}",
+                collection_name, e
+            ));
+        }
+
+        Ok(())
     }
 
-    pub async fn run(mut self) {
-        println!("Batch actor is running");
+    // Stops at the first failed write, the offsets of its batch and every later one stay
+    // uncommitted and the consumer exits
+    pub async fn run(mut self) -> Result<(), String> {
+        tracing::info!("Batch actor is running");
         while let Some(msg) = self.receiver.recv().await {
-            self.handle_message(msg).await
+            self.handle_message(msg).await?;
         }
+
+        Ok(())
     }
 }
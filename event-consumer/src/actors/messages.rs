@@ -1,10 +1,22 @@
-use crate::model::{Transaction, TransactionType};
+use opentelemetry::{trace::SpanContext, Context};
+
+use crate::{
+    model::{Transaction, TransactionType},
+    offsets::MessagePosition,
+};
 
 pub struct StateMessage {
     pub single_data: Transaction,
+    // Context of the span that consumed the message
+    pub context: Context,
+    pub position: MessagePosition,
 }
 
 pub struct BatchMessage {
     pub data_type: TransactionType,
     pub batch_data: Vec<Transaction>,
+    // Spans of the messages the batch was built from
+    pub links: Vec<SpanContext>,
+    // Reported back once the batch is written, their offsets are committed then
+    pub positions: Vec<MessagePosition>,
 }
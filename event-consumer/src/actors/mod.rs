@@ -1,3 +1,4 @@
+pub mod archive;
 pub mod batch;
 pub mod messages;
 pub mod state;
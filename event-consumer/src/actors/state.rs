@@ -1,83 +1,151 @@
-use std::{collections::HashMap, time::Duration};
+use opentelemetry::trace::{SpanContext, TraceContextExt};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tokio::{
     sync::mpsc::{Receiver, Sender},
     time::interval,
 };
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::actors::messages::{BatchMessage, StateMessage};
+use crate::metrics::Metrics;
 use crate::model::{Transaction, TransactionType};
+use crate::offsets::MessagePosition;
 
 const INTERVAL: u64 = 60;
+
+// The batch actor dropped its receiver after a failed write
+#[derive(Debug)]
+pub struct BatchActorStopped;
 const MAX_CACHE: usize = 100;
 
 pub struct StateActor {
     pub cache: HashMap<TransactionType, Vec<Transaction>>,
+    // Span contexts of the cached transactions, linked from the batch they end up in
+    pub links: HashMap<TransactionType, Vec<SpanContext>>,
+    pub positions: HashMap<TransactionType, Vec<MessagePosition>>,
     pub receiver: Receiver<StateMessage>,
     pub sender: Sender<BatchMessage>,
+    pub metrics: Arc<Metrics>,
 }
 
 impl StateActor {
-    pub fn new(receiver: Receiver<StateMessage>, sender: Sender<BatchMessage>) -> StateActor {
+    pub fn new(
+        receiver: Receiver<StateMessage>,
+        sender: Sender<BatchMessage>,
+        metrics: Arc<Metrics>,
+    ) -> StateActor {
         let cache: HashMap<TransactionType, Vec<Transaction>> = HashMap::new();
 
         StateActor {
             cache,
+            links: HashMap::new(),
+            positions: HashMap::new(),
             receiver,
             sender,
+            metrics,
         }
     }
 
-    async fn handle_message(&mut self, message: StateMessage) {
-        println!("State actor received a message");
+    #[tracing::instrument(name = "Caching transaction", skip_all)]
+    async fn handle_message(&mut self, message: StateMessage) -> Result<(), BatchActorStopped> {
+        tracing::Span::current().set_parent(message.context.clone());
 
-        let key = message.single_data.transaction_type.clone();
+        let key = message.single_data.transaction_type;
+        self.links
+            .entry(key)
+            .or_default()
+            .push(message.context.span().span_context().clone());
+        self.positions
+            .entry(key)
+            .or_default()
+            .push(message.position);
 
-        self.cache.entry(key.clone()).or_insert_with(Vec::new);
+        self.cache.entry(key).or_insert_with(Vec::new);
 
         if let Some(transactions) = self.cache.get_mut(&key) {
             transactions.push(message.single_data);
+            self.metrics
+                .cache_size
+                .with_label_values(&[&key.to_string()])
+                .set(transactions.len() as i64);
 
             if transactions.len() >= MAX_CACHE {
-                self.flush_cache_bucket(key).await;
+                self.metrics.flushes.with_label_values(&["size"]).inc();
+                return self.flush_cache_bucket(key).await;
             }
         }
+
+        Ok(())
     }
 
-    async fn flush_cache_bucket(&mut self, key: TransactionType) {
+    #[tracing::instrument(name = "Flushing cache", skip(self), fields(reason = "size"))]
+    async fn flush_cache_bucket(&mut self, key: TransactionType) -> Result<(), BatchActorStopped> {
         if let Some(transactions) = self.cache.get_mut(&key) {
             let batch_data = std::mem::take(transactions);
-
-            let message = BatchMessage {
-                data_type: key,
-                batch_data,
-            };
-            let _ = self.sender.send(message).await;
+            self.send_batch(key, batch_data).await?;
         }
+
+        Ok(())
     }
 
-    async fn flush_cache(&mut self) {
-        for (k, v) in self.cache.drain().collect::<HashMap<_, _>>() {
-            let message = BatchMessage {
-                data_type: k,
-                batch_data: v,
-            };
+    #[tracing::instrument(name = "Flushing cache", skip(self), fields(reason = "interval"))]
+    async fn flush_cache(&mut self) -> Result<(), BatchActorStopped> {
+        // Types emptied by a size flush keep their entry, nothing is sent for them
+        let batches = self
+            .cache
+            .drain()
+            .filter(|(_, transactions)| !transactions.is_empty())
+            .collect::<HashMap<_, _>>();
+        if batches.is_empty() {
+            return Ok(());
+        }
+        self.metrics.flushes.with_label_values(&["interval"]).inc();
 
-            let _ = self.sender.send(message).await;
+        for (k, v) in batches {
+            self.send_batch(k, v).await?;
         }
+
+        Ok(())
     }
 
+    async fn send_batch(
+        &mut self,
+        key: TransactionType,
+        batch_data: Vec<Transaction>,
+    ) -> Result<(), BatchActorStopped> {
+        self.metrics
+            .cache_size
+            .with_label_values(&[&key.to_string()])
+            .set(0);
+        self.metrics.batch_size.observe(batch_data.len() as f64);
+        tracing::info!(transaction_type = %key, batch_size = batch_data.len(), "Sending batch");
+
+        let message = BatchMessage {
+            links: self.links.remove(&key).unwrap_or_default(),
+            positions: self.positions.remove(&key).unwrap_or_default(),
+            data_type: key,
+            batch_data,
+        };
+
+        self.sender
+            .send(message)
+            .await
+            .map_err(|_| BatchActorStopped)
+    }
+
+    // Returns once the batch actor stopped, the cached transactions are never committed
     pub async fn run(mut self) {
-        println!("State actor is running");
+        tracing::info!("State actor is running");
 
         let mut interval_timer = interval(Duration::from_secs(INTERVAL));
         loop {
-            tokio::select! {
-                _ = interval_timer.tick() => {
-                    self.flush_cache().await;
-                },
-                Some(msg) = self.receiver.recv() => {
-                    self.handle_message(msg).await
-                }
+            let result = tokio::select! {
+                _ = interval_timer.tick() => self.flush_cache().await,
+                Some(msg) = self.receiver.recv() => self.handle_message(msg).await,
+            };
+            if let Err(BatchActorStopped) = result {
+                tracing::error!("Batch actor stopped, state actor is stopping");
+                return;
             }
         }
     }
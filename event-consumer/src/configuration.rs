@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use config::Config;
+use serde::Deserialize;
+use transactions_store::{
+    connection::{connection_string, DatabaseTlsSettings},
+    kafka::KafkaSettings,
+    layout::CollectionLayout,
+};
+
+#[derive(Deserialize)]
+pub struct Settings {
+    pub database: DatabaseSettings,
+    pub kafka: KafkaSettings,
+    pub retention: RetentionSettings,
+    pub metrics_port: u16,
+    // Receives messages that cannot be stored, e.g. of an unknown transaction type
+    #[serde(default = "default_dead_letter_topic")]
+    pub dead_letter_topic: String,
+}
+
+fn default_dead_letter_topic() -> String {
+    "transactions-dead-letter".to_string()
+}
+
+#[derive(Deserialize)]
+pub struct DatabaseSettings {
+    pub username: String,
+    pub password: String,
+    pub port: u16,
+    pub host: String,
+    pub bucket_name: String,
+    pub scope_name: String,
+    #[serde(default)]
+    pub layout: CollectionLayout,
+    pub tls: Option<DatabaseTlsSettings>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RetentionSettings {
+    #[serde(default)]
+    pub days: HashMap<String, u64>,
+    pub archive: ArchiveSettings,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ArchiveSettings {
+    pub directory: String,
+    pub lead_time_days: u64,
+    pub interval_secs: u64,
+}
+
+impl RetentionSettings {
+    // Absolute expiry in seconds since epoch, 0 keeps the document forever. Late or replayed
+    // events expire no earlier than the lead time ahead of `now`, so the archive still sees them.
+    pub fn expiry(&self, type_name: &str, timestamp: u64, now: u64) -> u64 {
+        match self.days.get(type_name) {
+            Some(days) => {
+                let expiry = timestamp / 1000 + days * 24 * 60 * 60;
+                expiry.max(now + self.archive.lead_time_days * 24 * 60 * 60)
+            }
+            None => 0,
+        }
+    }
+}
+
+impl DatabaseSettings {
+    pub fn connection_string(&self) -> String {
+        connection_string(&self.host, self.port, self.tls.as_ref())
+    }
+}
+
+pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+    let settings = Config::builder()
+        .add_source(config::File::with_name("configuration"))
+        .build()?;
+
+    let mut settings: Settings = settings.try_deserialize()?;
+    settings
+        .kafka
+        .load()
+        .map_err(config::ConfigError::Message)?;
+
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+    // 2024-03-01T00:00:00Z
+    const NOW: u64 = 1_709_251_200;
+
+    fn retention() -> RetentionSettings {
+        RetentionSettings {
+            days: HashMap::from([("bet".to_string(), 90)]),
+            archive: ArchiveSettings {
+                directory: "archive".to_string(),
+                lead_time_days: 7,
+                interval_secs: 3600,
+            },
+        }
+    }
+
+    #[test]
+    fn types_without_retention_never_expire() {
+        assert_eq!(0, retention().expiry("deposit", NOW * 1000, NOW));
+    }
+
+    #[test]
+    fn expiry_is_the_event_time_plus_retention() {
+        let timestamp = (NOW - 10 * DAY) * 1000;
+
+        assert_eq!(NOW + 80 * DAY, retention().expiry("bet", timestamp, NOW));
+    }
+
+    #[test]
+    fn late_events_expire_after_the_archive_lead_time() {
+        let timestamp = (NOW - 365 * DAY) * 1000;
+
+        assert_eq!(NOW + 7 * DAY, retention().expiry("bet", timestamp, NOW));
+    }
+
+    #[test]
+    fn events_near_their_expiry_still_reach_the_archive_window() {
+        let timestamp = (NOW - 88 * DAY) * 1000;
+
+        assert_eq!(NOW + 7 * DAY, retention().expiry("bet", timestamp, NOW));
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use rdkafka::{
+    message::{BorrowedMessage, Header, Headers, OwnedHeaders},
+    producer::{FutureProducer, FutureRecord},
+    Message,
+};
+use tokio::time::sleep;
+
+// Records why a message was dead-lettered, next to the headers it arrived with
+pub const REASON_HEADER: &str = "dead-letter-reason";
+
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// Messages the consumer cannot store are forwarded here unchanged instead of being dropped
+pub struct DeadLetterQueue {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl DeadLetterQueue {
+    pub fn new(producer: FutureProducer, topic: String) -> Self {
+        DeadLetterQueue { producer, topic }
+    }
+
+    // Retried until delivered, the offset of the message is only committed afterwards
+    pub async fn send(&self, message: &BorrowedMessage<'_>, reason: &str) {
+        loop {
+            let headers = message
+                .headers()
+                .map(|headers| headers.detach())
+                .unwrap_or_default()
+                .insert(Header {
+                    key: REASON_HEADER,
+                    value: Some(reason),
+                });
+            let record = FutureRecord {
+                topic: &self.topic,
+                partition: None,
+                payload: message.payload(),
+                key: message.key(),
+                timestamp: message.timestamp().to_millis(),
+                headers: Some(headers),
+            };
+
+            match self.producer.send(record, Duration::from_secs(0)).await {
+                Ok((partition, offset)) => {
+                    tracing::warn!(
+                        topic = %self.topic,
+                        partition,
+                        offset,
+                        reason,
+                        "Message sent to the dead-letter topic"
+                    );
+                    return;
+                }
+                Err((e, _)) => {
+                    tracing::error!("Error sending message to the dead-letter topic: {}", e);
+                    sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+}
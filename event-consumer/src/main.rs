@@ -1,72 +1,319 @@
-use actors::{batch::BatchActor, messages::BatchMessage, state::StateActor};
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actors::{archive::ArchiveActor, batch::BatchActor, messages::BatchMessage, state::StateActor};
+use configuration::get_configuration;
+use couchbase::Cluster;
+use dead_letter::DeadLetterQueue;
+use metrics::{Metrics, MetricsContext};
+use offsets::{MessagePosition, OffsetTracker};
 use rdkafka::{
     consumer::{CommitMode, Consumer, StreamConsumer},
-    ClientConfig, Message,
+    message::BorrowedMessage,
+    producer::FutureProducer,
+    Message, Offset, TopicPartitionList,
+};
+use service_telemetry::{
+    get_subscriber, get_tracer, init_subscriber, kafka::extract_context, shutdown_tracer,
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::mpsc::{self, Sender},
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use transactions_store::{
+    migrations::run_migrations,
+    provisioning::{provision, Schema},
 };
-use tokio::sync::mpsc;
 
-use crate::{actors::messages::StateMessage, model::Transaction};
+use crate::{
+    actors::messages::StateMessage,
+    model::{Transaction, TransactionType},
+};
 
 mod actors;
+mod configuration;
+mod dead_letter;
+mod metrics;
 mod model;
+mod offsets;
 
 #[tokio::main]
 async fn main() {
+    let tracer = get_tracer("event-consumer".into());
+    let subscriber = get_subscriber(
+        "event-consumer".into(),
+        "info".into(),
+        std::io::stdout,
+        tracer,
+    );
+    init_subscriber(subscriber);
+
+    let configuration = match get_configuration() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            tracing::error!("Failed to read configuration: {}", e);
+            shutdown_tracer();
+            std::process::exit(1);
+        }
+    };
+    let database = configuration.database;
+    let retention = configuration.retention;
+
+    let metrics = Arc::new(Metrics::new());
+    let metrics_server = metrics::serve(metrics.clone(), configuration.metrics_port)
+        .expect("Failed to bind the metrics port");
+    tokio::spawn(metrics_server);
+
+    let cluster = Arc::new(Cluster::connect(
+        &database.connection_string(),
+        &database.username,
+        &database.password,
+    ));
+
+    let type_names = TransactionType::ALL
+        .iter()
+        .map(|transaction_type| transaction_type.to_string())
+        .collect::<Vec<_>>();
+    let schema = Schema {
+        bucket_name: database.bucket_name.clone(),
+        scope_name: database.scope_name.clone(),
+        collections: database
+            .layout
+            .initial_collections(&type_names, now_millis()),
+    };
+    provision(&cluster, &schema)
+        .await
+        .expect("Schema provisioning failed");
+    run_migrations(&cluster, &schema)
+        .await
+        .expect("Schema migration failed");
+
     let (state_tx, state_rx) = mpsc::channel::<StateMessage>(1);
     let (batch_tx, batch_rx) = mpsc::channel::<BatchMessage>(1);
+    // Unbounded, the batch actor must not wait on the consumer loop waiting on the state actor
+    let (written_tx, mut written_rx) = mpsc::unbounded_channel::<Vec<MessagePosition>>();
 
-    tokio::spawn(async move {
-        let state_actor = StateActor::new(state_rx, batch_tx);
+    let state_metrics = metrics.clone();
+    let mut state_task = tokio::spawn(async move {
+        let state_actor = StateActor::new(state_rx, batch_tx, state_metrics);
         state_actor.run().await;
     });
 
+    let archive_actor = ArchiveActor::new(cluster.clone(), &database, retention.archive.clone());
     tokio::spawn(async move {
-        let batch_actor = BatchActor::new(batch_rx);
-        batch_actor.run().await;
+        archive_actor.run().await;
+    });
+
+    let batch_metrics = metrics.clone();
+    let mut batch_task = tokio::spawn(async move {
+        let batch_actor = BatchActor::new(
+            cluster,
+            &database,
+            retention,
+            batch_rx,
+            written_tx,
+            batch_metrics,
+        );
+        batch_actor.run().await
     });
 
     let transactions_str = "transactions";
 
-    let consumer: StreamConsumer = ClientConfig::new()
+    let consumer: StreamConsumer<MetricsContext> = configuration
+        .kafka
+        .client_config()
         .set("group.id", "transaction_group")
-        .set("bootstrap.servers", "localhost:29092")
-        .set("enable.auto.commit", "true")
-        .create()
+        // Offsets are committed once their transactions are written, see OffsetTracker
+        .set("enable.auto.commit", "false")
+        // Statistics carry the per partition consumer lag
+        .set("statistics.interval.ms", "15000")
+        .create_with_context(MetricsContext {
+            metrics: metrics.clone(),
+        })
         .expect("Consumer creation failed");
 
     consumer
         .subscribe(&[transactions_str])
         .expect("Topic subscription failed");
 
-    println!("Waiting for messages");
+    let dead_letter_producer: FutureProducer = configuration
+        .kafka
+        .client_config()
+        .create()
+        .expect("Producer creation failed");
+    let dead_letter = DeadLetterQueue::new(dead_letter_producer, configuration.dead_letter_topic);
+
+    tracing::info!("Waiting for messages");
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    let mut offsets = OffsetTracker::default();
+    let mut failed = false;
 
     loop {
-        match consumer.recv().await {
-            Ok(message) => {
-                let payload = match message.payload_view::<str>() {
-                    None => "",
-                    Some(Ok(s)) => s,
-                    Some(Err(e)) => {
-                        println!("Error while deserializing message payload: {:?}", e);
-                        ""
+        tokio::select! {
+            // The state actor stops after the batch actor, whose error is reported first
+            biased;
+            _ = &mut shutdown => break,
+            // Stops consuming, transactions that were not written are consumed again on restart
+            result = &mut batch_task => {
+                match result {
+                    Ok(Ok(())) => tracing::error!("Batch actor stopped"),
+                    Ok(Err(e)) => tracing::error!("Batch actor failed: {}", e),
+                    Err(e) => tracing::error!("Batch actor panicked: {}", e),
+                }
+                failed = true;
+                break;
+            }
+            _ = &mut state_task => {
+                tracing::error!("State actor stopped");
+                failed = true;
+                break;
+            }
+            Some(positions) = written_rx.recv() => {
+                for position in &positions {
+                    offsets.completed(position);
+                }
+                commit(&consumer, &mut offsets);
+            }
+            received = consumer.recv() => match received {
+                Ok(message) => {
+                    let position = MessagePosition::of(&message);
+                    offsets.received(&position);
+
+                    let forwarded =
+                        handle_message(&message, &position, &state_tx, &dead_letter, &metrics)
+                            .await;
+                    if !forwarded {
+                        offsets.completed(&position);
                     }
-                };
+                    commit(&consumer, &mut offsets);
+                }
+                Err(e) => tracing::error!("Kafka error: {}", e),
+            },
+        }
+    }
+
+    tracing::info!("Shutting down");
+    // Exports the spans still waiting in the batch exporter
+    shutdown_tracer();
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn commit(consumer: &StreamConsumer<MetricsContext>, offsets: &mut OffsetTracker) {
+    let mut list = TopicPartitionList::new();
+    for position in offsets.take_committable() {
+        if let Err(e) = list.add_partition_offset(
+            &position.topic,
+            position.partition,
+            Offset::Offset(position.offset),
+        ) {
+            tracing::error!("Invalid offset {:?}: {}", position, e);
+        }
+    }
+
+    if list.count() > 0 {
+        if let Err(e) = consumer.commit(&list, CommitMode::Async) {
+            tracing::error!("Error committing offsets: {}", e);
+        }
+    }
+}
+
+// Ctrl+C or SIGTERM, e.g. from `docker stop`
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+}
+
+#[tracing::instrument(
+    name = "Consuming message",
+    skip_all,
+    fields(
+        otel.kind = "consumer",
+        topic = message.topic(),
+        partition = message.partition(),
+        offset = message.offset(),
+        key = message.key_view::<str>().and_then(Result::ok),
+    )
+)]
+// Returns whether the transaction was passed on to be written, other messages are settled
+async fn handle_message(
+    message: &BorrowedMessage<'_>,
+    position: &MessagePosition,
+    state_tx: &Sender<StateMessage>,
+    dead_letter: &DeadLetterQueue,
+    metrics: &Metrics,
+) -> bool {
+    // Continues the trace started by the producer
+    tracing::Span::current().set_parent(extract_context(message.headers()));
+
+    metrics
+        .messages_consumed
+        .with_label_values(&[message.topic(), &message.partition().to_string()])
+        .inc();
 
-                let transaction: Transaction =
-                    serde_json::from_str(payload).expect("Error deserializing the message");
-                println!("Received transaction: {:?}", transaction);
+    let payload = match message.payload_view::<str>() {
+        None => "",
+        Some(Ok(s)) => s,
+        Some(Err(e)) => {
+            tracing::error!("Error while deserializing message payload: {:?}", e);
+            ""
+        }
+    };
 
-                let state_message = StateMessage {
-                    single_data: transaction,
-                };
+    match serde_json::from_str::<Transaction>(payload) {
+        // Kept for a consumer that knows the type instead of being committed unprocessed
+        Ok(transaction) if transaction.transaction_type == TransactionType::Unknown => {
+            tracing::warn!(payload, "Transaction of unknown type");
+            dead_letter.send(message, "unknown transaction type").await;
+            false
+        }
+        Ok(mut transaction) => {
+            if transaction.timestamp.is_none() {
+                transaction.timestamp = Some(
+                    message
+                        .timestamp()
+                        .to_millis()
+                        .map(|millis| millis as u64)
+                        .unwrap_or_else(now_millis),
+                );
+            }
+            tracing::info!(?transaction, "Received transaction");
 
-                state_tx.send(state_message).await.unwrap();
+            let state_message = StateMessage {
+                single_data: transaction,
+                context: tracing::Span::current().context(),
+                position: position.clone(),
+            };
 
-                consumer
-                    .commit_message(&message, CommitMode::Async)
-                    .unwrap();
+            // A stopped state actor is noticed by the consumer loop, the offset stays pending
+            if state_tx.send(state_message).await.is_err() {
+                tracing::error!("State actor stopped, transaction is not written");
             }
-            Err(e) => println!("Kafka error: {}", e),
+            true
+        }
+        Err(e) => {
+            metrics.deserialization_failures.inc();
+            tracing::error!("Error deserializing the message: {:?}", e);
+            dead_letter.send(message, "malformed payload").await;
+            false
         }
     }
 }
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before unix epoch")
+        .as_millis() as u64
+}
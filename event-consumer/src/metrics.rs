@@ -0,0 +1,190 @@
+use std::{convert::Infallible, future::Future, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use rdkafka::{consumer::ConsumerContext, ClientContext, Statistics};
+
+pub struct Metrics {
+    registry: Registry,
+    pub messages_consumed: IntCounterVec,
+    pub deserialization_failures: IntCounter,
+    pub cache_size: IntGaugeVec,
+    pub flushes: IntCounterVec,
+    pub batch_size: Histogram,
+    pub write_duration: HistogramVec,
+    pub write_failures: IntCounterVec,
+    pub consumer_lag: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("event_consumer".to_string()), None)
+            .expect("Metrics registry creation failed");
+
+        let messages_consumed = IntCounterVec::new(
+            Opts::new("messages_consumed_total", "Kafka messages consumed"),
+            &["topic", "partition"],
+        )
+        .unwrap();
+        let deserialization_failures = IntCounter::new(
+            "deserialization_failures_total",
+            "Messages whose payload could not be deserialized into a transaction",
+        )
+        .unwrap();
+        let cache_size = IntGaugeVec::new(
+            Opts::new(
+                "cache_size",
+                "Transactions held by the state actor waiting for a flush",
+            ),
+            &["transaction_type"],
+        )
+        .unwrap();
+        let flushes = IntCounterVec::new(
+            Opts::new("flushes_total", "Cache flushes of the state actor"),
+            &["reason"],
+        )
+        .unwrap();
+        let batch_size = Histogram::with_opts(
+            HistogramOpts::new(
+                "batch_size",
+                "Transactions per batch sent to the batch actor",
+            )
+            .buckets(vec![1.0, 5.0, 10.0, 25.0, 50.0, 75.0, 100.0]),
+        )
+        .unwrap();
+        let write_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "write_duration_seconds",
+                "Latency of batch inserts into couchbase",
+            ),
+            &["collection"],
+        )
+        .unwrap();
+        let write_failures = IntCounterVec::new(
+            Opts::new(
+                "write_failures_total",
+                "Failed batch inserts into couchbase",
+            ),
+            &["collection"],
+        )
+        .unwrap();
+        let consumer_lag = IntGaugeVec::new(
+            Opts::new(
+                "consumer_lag",
+                "Messages between the committed offset and the end of a partition",
+            ),
+            &["topic", "partition"],
+        )
+        .unwrap();
+
+        registry
+            .register(Box::new(messages_consumed.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(deserialization_failures.clone()))
+            .unwrap();
+        registry.register(Box::new(cache_size.clone())).unwrap();
+        registry.register(Box::new(flushes.clone())).unwrap();
+        registry.register(Box::new(batch_size.clone())).unwrap();
+        registry.register(Box::new(write_duration.clone())).unwrap();
+        registry.register(Box::new(write_failures.clone())).unwrap();
+        registry.register(Box::new(consumer_lag.clone())).unwrap();
+
+        Metrics {
+            registry,
+            messages_consumed,
+            deserialization_failures,
+            cache_size,
+            flushes,
+            batch_size,
+            write_duration,
+            write_failures,
+            consumer_lag,
+        }
+    }
+
+    fn encode(&self) -> Response<Body> {
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+
+        match encoder.encode(&self.registry.gather(), &mut buffer) {
+            Ok(()) => Response::builder()
+                .header(CONTENT_TYPE, encoder.format_type())
+                .body(Body::from(buffer))
+                .unwrap(),
+            Err(e) => {
+                tracing::error!("Error encoding metrics: {}", e);
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::empty())
+                    .unwrap()
+            }
+        }
+    }
+}
+
+// Serves the registry on `/metrics`, every other path is a 404. The port is bound before
+// returning so a port in use fails startup instead of the spawned server
+pub fn serve(metrics: Arc<Metrics>, port: u16) -> Result<impl Future<Output = ()>, hyper::Error> {
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let metrics = metrics.clone();
+                async move {
+                    let response = if request.uri().path() == "/metrics" {
+                        metrics.encode()
+                    } else {
+                        Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty())
+                            .unwrap()
+                    };
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)?.serve(make_service);
+    tracing::info!("Serving metrics on {}", address);
+
+    Ok(async move {
+        if let Err(e) = server.await {
+            tracing::error!("Metrics server error: {}", e);
+        }
+    })
+}
+
+// librdkafka reports partition lag through its periodic statistics
+pub struct MetricsContext {
+    pub metrics: Arc<Metrics>,
+}
+
+impl ClientContext for MetricsContext {
+    fn stats(&self, statistics: Statistics) {
+        for (topic_name, topic) in statistics.topics {
+            for (partition_id, partition) in topic.partitions {
+                // Internal partition -1 and unassigned partitions report a negative lag
+                if partition_id < 0 || partition.consumer_lag < 0 {
+                    continue;
+                }
+                self.metrics
+                    .consumer_lag
+                    .with_label_values(&[&topic_name, &partition_id.to_string()])
+                    .set(partition.consumer_lag);
+            }
+        }
+    }
+}
+
+impl ConsumerContext for MetricsContext {}
@@ -0,0 +1,128 @@
+use std::collections::{BTreeSet, HashMap};
+
+use rdkafka::{message::BorrowedMessage, Message};
+
+// Where a consumed message sits in its topic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessagePosition {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+impl MessagePosition {
+    pub fn of(message: &BorrowedMessage<'_>) -> Self {
+        MessagePosition {
+            topic: message.topic().to_string(),
+            partition: message.partition(),
+            offset: message.offset(),
+        }
+    }
+}
+
+// Offsets are committed once every message before them is stored or dead-lettered. Batches
+// of different types are written out of order, so a partition's commit stops at the oldest
+// message still waiting for its batch.
+#[derive(Debug, Default)]
+pub struct OffsetTracker {
+    partitions: HashMap<(String, i32), PartitionOffsets>,
+}
+
+#[derive(Debug, Default)]
+struct PartitionOffsets {
+    pending: BTreeSet<i64>,
+    // Offset after the last consumed message
+    next: i64,
+    committed: Option<i64>,
+}
+
+impl OffsetTracker {
+    pub fn received(&mut self, position: &MessagePosition) {
+        let partition = self
+            .partitions
+            .entry((position.topic.clone(), position.partition))
+            .or_default();
+        partition.pending.insert(position.offset);
+        partition.next = partition.next.max(position.offset + 1);
+    }
+
+    pub fn completed(&mut self, position: &MessagePosition) {
+        if let Some(partition) = self
+            .partitions
+            .get_mut(&(position.topic.clone(), position.partition))
+        {
+            partition.pending.remove(&position.offset);
+        }
+    }
+
+    // Offsets to commit per topic and partition, each is the next message to consume.
+    // Partitions whose offset did not move since the last call are left out.
+    pub fn take_committable(&mut self) -> Vec<MessagePosition> {
+        let mut committable = vec![];
+        for ((topic, partition), offsets) in &mut self.partitions {
+            let offset = offsets.pending.first().copied().unwrap_or(offsets.next);
+            if offsets.committed != Some(offset) {
+                offsets.committed = Some(offset);
+                committable.push(MessagePosition {
+                    topic: topic.clone(),
+                    partition: *partition,
+                    offset,
+                });
+            }
+        }
+
+        committable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(partition: i32, offset: i64) -> MessagePosition {
+        MessagePosition {
+            topic: "transactions".to_string(),
+            partition,
+            offset,
+        }
+    }
+
+    #[test]
+    fn commits_stay_before_pending_messages() {
+        let mut tracker = OffsetTracker::default();
+        tracker.received(&position(0, 0));
+        tracker.received(&position(0, 1));
+
+        assert_eq!(vec![position(0, 0)], tracker.take_committable());
+        assert!(tracker.take_committable().is_empty());
+    }
+
+    #[test]
+    fn commits_stop_at_the_oldest_pending_message() {
+        let mut tracker = OffsetTracker::default();
+        for offset in 10..13 {
+            tracker.received(&position(0, offset));
+        }
+        tracker.take_committable();
+
+        // A later batch is written first
+        tracker.completed(&position(0, 11));
+        tracker.completed(&position(0, 12));
+        assert!(tracker.take_committable().is_empty());
+
+        tracker.completed(&position(0, 10));
+        assert_eq!(vec![position(0, 13)], tracker.take_committable());
+    }
+
+    #[test]
+    fn partitions_are_committed_independently() {
+        let mut tracker = OffsetTracker::default();
+        tracker.received(&position(0, 5));
+        tracker.received(&position(1, 7));
+        tracker.take_committable();
+
+        tracker.completed(&position(1, 7));
+
+        assert_eq!(vec![position(1, 8)], tracker.take_committable());
+    }
+}
@@ -0,0 +1,22 @@
+use config::Config;
+use serde::Deserialize;
+use transactions_store::kafka::KafkaSettings;
+
+#[derive(Deserialize)]
+pub struct Settings {
+    pub kafka: KafkaSettings,
+}
+
+pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+    let settings = Config::builder()
+        .add_source(config::File::with_name("configuration"))
+        .build()?;
+
+    let mut settings: Settings = settings.try_deserialize()?;
+    settings
+        .kafka
+        .load()
+        .map_err(config::ConfigError::Message)?;
+
+    Ok(settings)
+}
@@ -1,11 +1,25 @@
-use tokio::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use rand::Rng;
-use rdkafka::{
-    producer::{FutureProducer, FutureRecord},
-    ClientConfig,
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    time::Duration,
 };
+
+use configuration::get_configuration;
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    Rng,
+};
+use rdkafka::producer::{FutureProducer, FutureRecord};
 use serde::{Deserialize, Serialize};
+use service_telemetry::{
+    get_subscriber, get_tracer, init_subscriber, kafka::inject_context, shutdown_tracer,
+};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use transactions_store::model::TransactionType;
+
+mod configuration;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Transaction {
@@ -13,72 +27,125 @@ struct Transaction {
     pub user_id: u64,
     pub amount: f64,
     pub transaction_type: TransactionType,
+    // Milliseconds since epoch
+    pub timestamp: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-enum TransactionType {
-    Bet,
-    Trade,
-    Deposit,
-    Withdrawal,
+// Relative frequency of every generated transaction type, types added later get the default
+fn weight(transaction_type: &TransactionType) -> u32 {
+    match transaction_type {
+        TransactionType::Bet => 40,
+        TransactionType::Trade => 20,
+        TransactionType::Deposit => 15,
+        TransactionType::Withdrawal => 10,
+        _ => 5,
+    }
 }
 
 #[tokio::main]
 async fn main() {
+    let tracer = get_tracer("event-producer".into());
+    let subscriber = get_subscriber(
+        "event-producer".into(),
+        "info".into(),
+        std::io::stdout,
+        tracer,
+    );
+    init_subscriber(subscriber);
+
+    let configuration = get_configuration().expect("Failed to read configuration.");
+    let producer: FutureProducer = configuration
+        .kafka
+        .client_config()
+        .create()
+        .expect("Producer creation error");
+
     let topic = "transactions";
 
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
     loop {
         let transaction = generate_transaction();
 
-        if let Err(e) = produce_event(&transaction, topic).await {
-            eprintln!("Error producing transaction: {}", e);
+        let span = tracing::info_span!(
+            "Producing transaction",
+            otel.kind = "producer",
+            key = %transaction.id,
+            transaction_type = ?transaction.transaction_type,
+            partition = tracing::field::Empty,
+            offset = tracing::field::Empty,
+        );
+
+        if let Err(e) = produce_event(&producer, &transaction, topic)
+            .instrument(span)
+            .await
+        {
+            tracing::error!("Error producing transaction: {}", e);
         }
 
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {},
+        }
+    }
+
+    tracing::info!("Shutting down");
+    // Exports the spans still waiting in the batch exporter
+    shutdown_tracer();
+}
+
+// Ctrl+C or SIGTERM, e.g. from `docker stop`
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
     }
 }
 
 fn generate_transaction() -> Transaction {
     let mut rng = rand::thread_rng();
 
-    let transaction_type = match rng.gen_range(0..4) {
-        0 => TransactionType::Bet,
-        1 => TransactionType::Trade,
-        2 => TransactionType::Deposit,
-        _ => TransactionType::Withdrawal,
-    };
+    let weights = WeightedIndex::new(TransactionType::ALL.iter().map(weight))
+        .expect("Invalid transaction type weights");
+    let transaction_type = TransactionType::ALL[weights.sample(&mut rng)];
 
     Transaction {
         id: rng.gen_range(1..1000000000),
         user_id: rng.gen_range(1..1000000000),
         amount: rng.gen_range(1.0..1000.0),
         transaction_type,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time is before unix epoch")
+            .as_millis() as u64,
     }
 }
 
 async fn produce_event(
+    producer: &FutureProducer,
     transaction: &Transaction,
     topic: &str,
 ) -> Result<(), rdkafka::error::KafkaError> {
-    //TODO: We create client for each produce event,
-    // Should be created once
-    let producer: FutureProducer = ClientConfig::new()
-        .set("bootstrap.servers", "localhost:29092")
-        .create()
-        .expect("Producer creation error");
-
     let payload = serde_json::to_string(&transaction).unwrap();
 
-    let status = producer
+    let (partition, offset) = producer
         .send(
             FutureRecord::to(topic)
                 .payload(&payload)
-                .key(&transaction.id.to_string()),
+                .key(&transaction.id.to_string())
+                // Lets the consumer continue this trace
+                .headers(inject_context(&tracing::Span::current().context())),
             Duration::from_secs(0),
         )
-        .await;
+        .await
+        .map_err(|(e, _)| e)?;
 
-    println!("Send event status: {:?}, payload: {}", status, payload);
+    let span = tracing::Span::current();
+    span.record("partition", partition);
+    span.record("offset", offset);
+    tracing::info!(%payload, "Transaction produced");
 
     Ok(())
 }
@@ -0,0 +1,90 @@
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    Context,
+};
+use rdkafka::message::{BorrowedHeaders, Header, Headers, OwnedHeaders};
+
+struct HeaderInjector(Option<OwnedHeaders>);
+
+impl Injector for HeaderInjector {
+    fn set(&mut self, key: &str, value: String) {
+        let headers = self.0.take().unwrap_or_default();
+        self.0 = Some(headers.insert(Header {
+            key,
+            value: Some(&value),
+        }));
+    }
+}
+
+struct HeaderExtractor<'a>(&'a BorrowedHeaders);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|header| header.key == key)
+            .and_then(|header| header.value)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|header| header.key).collect()
+    }
+}
+
+// Headers carrying `traceparent` (and `tracestate`) of the given context
+pub fn inject_context(context: &Context) -> OwnedHeaders {
+    let mut injector = HeaderInjector(None);
+    global::get_text_map_propagator(|propagator| propagator.inject_context(context, &mut injector));
+
+    injector.0.unwrap_or_default()
+}
+
+// Messages without trace headers yield an empty context, starting a new trace
+pub fn extract_context(headers: Option<&BorrowedHeaders>) -> Context {
+    match headers {
+        Some(headers) => global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(headers))
+        }),
+        None => Context::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+
+    #[test]
+    fn context_round_trips_through_headers() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let span_context = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let context = Context::new().with_remote_span_context(span_context.clone());
+
+        let headers = inject_context(&context);
+        let extracted = extract_context(Some(headers.as_borrowed()));
+
+        assert_eq!(&span_context, extracted.span().span_context());
+    }
+
+    #[test]
+    fn messages_without_headers_start_a_new_trace() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        assert!(!extract_context(None).span().span_context().is_valid());
+        assert!(!extract_context(Some(OwnedHeaders::new().as_borrowed()))
+            .span()
+            .span_context()
+            .is_valid());
+    }
+}
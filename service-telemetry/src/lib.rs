@@ -0,0 +1,82 @@
+use opentelemetry::{global, trace::TracerProvider as _, KeyValue};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{self, Tracer, TracerProvider},
+    Resource,
+};
+use tracing::{subscriber::set_global_default, Subscriber};
+use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_log::LogTracer;
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+
+pub fn get_subscriber<Sink>(
+    name: String,
+    env_filter: String,
+    sink: Sink,
+    tracer: Tracer,
+) -> impl Subscriber + Send + Sync
+where
+    Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    // Sets info level as defauld if level not specified by RUST_LOG environment variable
+    let env_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+
+    // Sends formatted JSON spans to sink
+    // in out case to: stdout
+    let formatting_layer = BunyanFormattingLayer::new(name, sink);
+
+    // Creates processing pipeline for logs from layers
+    Registry::default()
+        .with(env_filter)
+        // Stores spans for further layers
+        .with(JsonStorageLayer)
+        .with(formatting_layer)
+        // Turns tracing spans into OpenTelemetry spans
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+// Spans are exported over OTLP when OTEL_EXPORTER_OTLP_ENDPOINT is set,
+// otherwise they are only created to propagate trace ids
+pub fn get_tracer(name: String) -> Tracer {
+    let config = trace::config().with_resource(Resource::new(vec![KeyValue::new(
+        "service.name",
+        name.clone(),
+    )]));
+
+    if std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_ok() {
+        // Exports from its own thread so it works on actix and tokio runtimes alike
+        return opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+            .with_trace_config(config)
+            .install_batch(runtime::TokioCurrentThread)
+            .expect("Failed to install OTLP tracer.");
+    }
+
+    let provider = TracerProvider::builder().with_config(config).build();
+    let tracer = provider.tracer(name);
+    global::set_tracer_provider(provider);
+
+    tracer
+}
+
+pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
+    // Collects all logs from libraries using the log crate (actix_web, rdkafka)
+    LogTracer::init().expect("Failed to set logger.");
+
+    // Trace context travels as W3C traceparent headers
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    // Register default subscriber to process logs
+    set_global_default(subscriber).expect("Failed to set subscriber");
+}
+
+// Flushes spans still waiting in the batch exporter
+pub fn shutdown_tracer() {
+    global::shutdown_tracer_provider();
+}
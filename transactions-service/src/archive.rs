@@ -0,0 +1,375 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use arrow::{
+    array::{Array, ArrayRef, Float64Array, StringArray, UInt64Array},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    record_batch::RecordBatch,
+};
+use chrono::{DateTime, NaiveDate};
+use parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter, ProjectionMask},
+    basic::Compression,
+    file::{
+        properties::WriterProperties,
+        reader::{FileReader, SerializedFileReader},
+    },
+};
+
+use crate::{model::StoredTransaction, model::TransactionType, repository, CouchbaseConnection};
+
+pub type ArchiveResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+#[derive(Debug, Default)]
+pub struct ArchiveSummary {
+    pub files: Vec<PathBuf>,
+    pub archived: u64,
+    pub deleted: u64,
+}
+
+// Moves transactions older than `before` out of Couchbase into
+// `{output}/transaction_type={type}/date={YYYY-MM-DD}/{collection}-{run}.parquet`.
+// Every run writes files of its own and never overwrites existing ones. Documents are only
+// deleted once the files of their dates, including those of earlier runs, hold every one of them.
+pub async fn archive_transactions(
+    connection_data: &CouchbaseConnection,
+    before: NaiveDate,
+    output: &Path,
+    delete: bool,
+) -> ArchiveResult<ArchiveSummary> {
+    let cutoff = before
+        .and_hms_opt(0, 0, 0)
+        .expect("Midnight is a valid time")
+        .and_utc()
+        .timestamp_millis() as u64;
+    let run = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before unix epoch")
+        .as_millis();
+
+    let mut summary = ArchiveSummary::default();
+
+    for transaction_type in TransactionType::ALL {
+        for partition in repository::partitions(connection_data, transaction_type).await? {
+            let rows = repository::transactions_before(connection_data, &partition, cutoff).await?;
+            if rows.is_empty() {
+                continue;
+            }
+
+            let expected = repository::count_before(connection_data, &partition, cutoff).await?;
+
+            let mut written = 0;
+            let mut directories = vec![];
+            for (date, rows) in group_by_date(&rows) {
+                let directory = output
+                    .join(format!("transaction_type={}", transaction_type))
+                    .join(format!("date={}", date));
+                fs::create_dir_all(&directory)?;
+
+                let path = directory.join(format!("{}-{}.parquet", partition.collection_name, run));
+                write_parquet(&path, &rows)?;
+
+                let rows_in_file = parquet_row_count(&path)?;
+                if rows_in_file != rows.len() as u64 {
+                    return Err(format!(
+                        "{} holds {} rows, expected {}",
+                        path.display(),
+                        rows_in_file,
+                        rows.len()
+                    )
+                    .into());
+                }
+
+                written += rows_in_file;
+                summary.files.push(path);
+                directories.push(directory);
+            }
+
+            tracing::info!(
+                collection = %partition.collection_name,
+                written,
+                expected,
+                "Archived transactions"
+            );
+            summary.archived += written;
+
+            if written != expected {
+                tracing::warn!(
+                    collection = %partition.collection_name,
+                    written,
+                    expected,
+                    "Row counts differ, keeping documents in the hot store"
+                );
+                continue;
+            }
+
+            if delete {
+                let archived = archived_ids(&directories, &partition.collection_name)?;
+                let missing = rows
+                    .iter()
+                    .filter(|row| !archived.contains(&row.id))
+                    .count();
+                if missing > 0 {
+                    tracing::warn!(
+                        collection = %partition.collection_name,
+                        missing,
+                        "Archive misses documents, keeping them in the hot store"
+                    );
+                    continue;
+                }
+
+                let ids = rows.iter().map(|row| row.id.clone()).collect::<Vec<_>>();
+                repository::delete_transactions(connection_data, &partition, &ids).await?;
+                summary.deleted += written;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+fn group_by_date(rows: &[StoredTransaction]) -> BTreeMap<NaiveDate, Vec<&StoredTransaction>> {
+    let mut groups: BTreeMap<NaiveDate, Vec<&StoredTransaction>> = BTreeMap::new();
+
+    for row in rows {
+        let date = row
+            .transaction
+            .timestamp
+            .and_then(|timestamp| DateTime::from_timestamp((timestamp / 1000) as i64, 0))
+            .map(|datetime| datetime.date_naive())
+            .unwrap_or_default();
+        groups.entry(date).or_default().push(row);
+    }
+
+    groups
+}
+
+fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("transaction_id", DataType::UInt64, false),
+        Field::new("user_id", DataType::UInt64, false),
+        Field::new("amount", DataType::Float64, false),
+        Field::new("transaction_type", DataType::Utf8, false),
+        Field::new("timestamp", DataType::UInt64, true),
+    ]))
+}
+
+fn write_parquet(path: &Path, rows: &[&StoredTransaction]) -> ArchiveResult<()> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| row.id.as_str()),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.transaction.id),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.transaction.user_id),
+        )),
+        Arc::new(Float64Array::from_iter_values(
+            rows.iter().map(|row| row.transaction.amount),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter()
+                .map(|row| row.transaction.transaction_type.to_string()),
+        )),
+        Arc::new(UInt64Array::from(
+            rows.iter()
+                .map(|row| row.transaction.timestamp)
+                .collect::<Vec<_>>(),
+        )),
+    ];
+
+    let batch = RecordBatch::try_new(schema(), columns)?;
+
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    // Fails instead of replacing the rows of an earlier run
+    let file = File::options().write(true).create_new(true).open(path)?;
+    let mut writer = ArrowWriter::try_new(file, schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+fn parquet_row_count(path: &Path) -> ArchiveResult<u64> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    Ok(reader.metadata().file_metadata().num_rows() as u64)
+}
+
+// Document keys in every file of a collection below the given date directories
+fn archived_ids(directories: &[PathBuf], collection_name: &str) -> ArchiveResult<HashSet<String>> {
+    let run_prefix = format!("{}-", collection_name);
+
+    let mut ids = HashSet::new();
+    for directory in directories {
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            let is_collection_file = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with(&run_prefix) && name.ends_with(".parquet"))
+                .unwrap_or(false);
+            if is_collection_file {
+                ids.extend(parquet_ids(&path)?);
+            }
+        }
+    }
+
+    Ok(ids)
+}
+
+// Decodes the id column only, the other columns are skipped
+fn parquet_ids(path: &Path) -> ArchiveResult<Vec<String>> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let id_column = builder
+        .parquet_schema()
+        .columns()
+        .iter()
+        .position(|column| column.name() == "id")
+        .ok_or_else(|| format!("{} has no id column", path.display()))?;
+    let projection = ProjectionMask::leaves(builder.parquet_schema(), [id_column]);
+    let reader = builder.with_projection(projection).build()?;
+
+    let mut ids = vec![];
+    for batch in reader {
+        let batch = batch?;
+        let column = batch
+            .column_by_name("id")
+            .and_then(|column| column.as_any().downcast_ref::<StringArray>())
+            .ok_or_else(|| format!("{} has no id column", path.display()))?;
+        ids.extend(
+            (0..column.len())
+                .filter(|index| column.is_valid(*index))
+                .map(|index| column.value(index).to_string()),
+        );
+    }
+
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Transaction;
+
+    fn stored(id: u64, timestamp: Option<u64>) -> StoredTransaction {
+        StoredTransaction {
+            id: format!("key-{}", id),
+            transaction: Transaction {
+                id,
+                user_id: 42,
+                amount: 10.0,
+                transaction_type: TransactionType::Deposit,
+                timestamp,
+            },
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("archive-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn rows_are_grouped_by_utc_date() {
+        // 2024-03-01T23:59:59Z, 2024-03-02T00:00:00Z and 2024-03-02T12:00:00Z
+        let rows = vec![
+            stored(1, Some(1_709_337_599_000)),
+            stored(2, Some(1_709_337_600_000)),
+            stored(3, Some(1_709_380_800_000)),
+            stored(4, None),
+        ];
+
+        let groups = group_by_date(&rows)
+            .into_iter()
+            .map(|(date, rows)| {
+                (
+                    date.to_string(),
+                    rows.iter()
+                        .map(|row| row.transaction.id)
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                ("1970-01-01".to_string(), vec![4]),
+                ("2024-03-01".to_string(), vec![1]),
+                ("2024-03-02".to_string(), vec![2, 3]),
+            ],
+            groups
+        );
+    }
+
+    #[test]
+    fn parquet_files_round_trip_their_rows() {
+        let directory = temp_dir();
+        let path = directory.join("deposit-1.parquet");
+        let rows = [stored(1, Some(1_709_337_599_000)), stored(2, None)];
+
+        write_parquet(&path, &rows.iter().collect::<Vec<_>>()).unwrap();
+
+        assert_eq!(2, parquet_row_count(&path).unwrap());
+        assert_eq!(vec!["key-1", "key-2"], parquet_ids(&path).unwrap());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn existing_files_are_not_overwritten() {
+        let directory = temp_dir();
+        let path = directory.join("deposit-1.parquet");
+        let first = [stored(1, None)];
+        let second = [stored(2, None)];
+
+        write_parquet(&path, &first.iter().collect::<Vec<_>>()).unwrap();
+        assert!(write_parquet(&path, &second.iter().collect::<Vec<_>>()).is_err());
+
+        assert_eq!(vec!["key-1"], parquet_ids(&path).unwrap());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn archived_ids_cover_every_run_of_a_collection() {
+        let directory = temp_dir();
+        let earlier = [stored(1, None)];
+        let later = [stored(2, None)];
+        let other = [stored(3, None)];
+        write_parquet(
+            &directory.join("deposit-1.parquet"),
+            &earlier.iter().collect::<Vec<_>>(),
+        )
+        .unwrap();
+        write_parquet(
+            &directory.join("deposit-2.parquet"),
+            &later.iter().collect::<Vec<_>>(),
+        )
+        .unwrap();
+        write_parquet(
+            &directory.join("deposit_2024_03-2.parquet"),
+            &other.iter().collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+        let ids = archived_ids(std::slice::from_ref(&directory), "deposit").unwrap();
+
+        assert_eq!(
+            HashSet::from(["key-1".to_string(), "key-2".to_string()]),
+            ids
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    collections::HashMap,
+    fs,
+    future::{ready, Future},
+    pin::Pin,
+};
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderValue},
+    web, Error, HttpMessage, HttpResponse,
+};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpan, RootSpanBuilder};
+
+use crate::{
+    configuration::{AuthSettings, JwtSettings},
+    rate_limit::{self, RateLimiter},
+    versioning::unversioned,
+};
+
+pub const READ_SCOPE: &str = "transactions:read";
+pub const EXPORT_SCOPE: &str = "transactions:export";
+// Grants access to the transactions of every user
+pub const ADMIN_SCOPE: &str = "transactions:admin";
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+// Routes anyone may call, e.g. orchestrator probes, the metrics scraper and the API docs
+const PUBLIC_ROUTES: &[&str] = &[
+    "/",
+    "/health/live",
+    "/health/ready",
+    "/metrics",
+    "/openapi.json",
+    "/swagger-ui/{_:.*}",
+];
+
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub subject: String,
+    pub scopes: Vec<String>,
+}
+
+impl Principal {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| granted == scope)
+    }
+
+    // Without the admin scope the subject has to be the user id of the caller
+    pub fn access(&self) -> Access {
+        if self.has_scope(ADMIN_SCOPE) {
+            return Access::All;
+        }
+
+        match self.subject.parse() {
+            Ok(user_id) => Access::User(user_id),
+            Err(_) => Access::Nothing,
+        }
+    }
+}
+
+// Transactions a principal may read, enforced by the repository queries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    All,
+    User(u64),
+    Nothing,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    // Space separated as in OAuth 2.0
+    #[serde(default)]
+    scope: String,
+}
+
+enum JwtKeys {
+    Hmac(DecodingKey),
+    Jwks(JwkSet),
+}
+
+pub struct Authenticator {
+    // Keyed by the api key itself
+    api_keys: HashMap<String, Principal>,
+    jwt_keys: Option<JwtKeys>,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl Authenticator {
+    pub fn new(settings: &AuthSettings) -> Result<Self, String> {
+        let api_keys = settings
+            .api_keys
+            .iter()
+            .map(|api_key| {
+                let key = api_key.key.as_ref().ok_or_else(|| {
+                    format!(
+                        "auth.api_keys of {} requires key or key_file",
+                        api_key.subject
+                    )
+                })?;
+
+                Ok((
+                    key.expose_secret().clone(),
+                    Principal {
+                        subject: api_key.subject.clone(),
+                        scopes: api_key.scopes.clone(),
+                    },
+                ))
+            })
+            .collect::<Result<_, String>>()?;
+
+        let jwt_keys = match &settings.jwt {
+            Some(jwt) => Some(Self::jwt_keys(jwt)?),
+            None => None,
+        };
+
+        Ok(Authenticator {
+            api_keys,
+            jwt_keys,
+            issuer: settings.jwt.as_ref().and_then(|jwt| jwt.issuer.clone()),
+            audience: settings.jwt.as_ref().and_then(|jwt| jwt.audience.clone()),
+        })
+    }
+
+    fn jwt_keys(settings: &JwtSettings) -> Result<JwtKeys, String> {
+        match (&settings.hmac_secret, &settings.jwks_file) {
+            (Some(secret), None) => Ok(JwtKeys::Hmac(DecodingKey::from_secret(
+                secret.expose_secret().as_bytes(),
+            ))),
+            (None, Some(jwks_file)) => {
+                let jwks = fs::read_to_string(jwks_file).map_err(|e| {
+                    format!("Failed to read JWKS from {}: {}", jwks_file.display(), e)
+                })?;
+                serde_json::from_str(&jwks)
+                    .map(JwtKeys::Jwks)
+                    .map_err(|e| format!("Invalid JWKS in {}: {}", jwks_file.display(), e))
+            }
+            _ => Err("Configure exactly one of auth.jwt.hmac_secret and auth.jwt.jwks_file".into()),
+        }
+    }
+
+    fn authenticate(&self, request: &ServiceRequest) -> Option<Principal> {
+        if let Some(key) = request.headers().get(API_KEY_HEADER) {
+            return self.api_keys.get(key.to_str().ok()?).cloned();
+        }
+
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")?;
+
+        match self.verify_token(token) {
+            Ok(principal) => Some(principal),
+            Err(e) => {
+                tracing::warn!("Rejected bearer token: {}", e);
+                None
+            }
+        }
+    }
+
+    fn verify_token(&self, token: &str) -> Result<Principal, jsonwebtoken::errors::Error> {
+        let (key, algorithm) = match &self.jwt_keys {
+            Some(JwtKeys::Hmac(key)) => (key.clone(), Algorithm::HS256),
+            Some(JwtKeys::Jwks(jwks)) => {
+                let header = decode_header(token)?;
+                let jwk = match &header.kid {
+                    Some(kid) => jwks.find(kid),
+                    None => jwks.keys.first(),
+                }
+                .ok_or(jsonwebtoken::errors::ErrorKind::InvalidToken)?;
+                // A key of another family than the algorithm of the token fails validation
+                (DecodingKey::from_jwk(jwk)?, header.alg)
+            }
+            None => return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into()),
+        };
+
+        let mut validation = Validation::new(algorithm);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let claims = decode::<Claims>(token, &key, &validation)?.claims;
+
+        Ok(Principal {
+            subject: claims.sub,
+            scopes: claims.scope.split_whitespace().map(String::from).collect(),
+        })
+    }
+}
+
+pub fn public_route(route: &str) -> bool {
+    PUBLIC_ROUTES.contains(&route)
+}
+
+fn required_scope(route: &str) -> Option<&'static str> {
+    if public_route(route) {
+        return None;
+    }
+
+    match unversioned(route) {
+        "/transactions/export" => Some(EXPORT_SCOPE),
+        _ => Some(READ_SCOPE),
+    }
+}
+
+// Used with `App::wrap_fn`, authenticated requests carry their `Principal` in the extensions
+pub fn authorize<S, B>(
+    request: ServiceRequest,
+    service: &S,
+) -> Pin<Box<dyn Future<Output = Result<ServiceResponse<EitherBody<B>>, Error>>>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let route = request
+        .match_pattern()
+        .unwrap_or_else(|| request.path().to_string());
+
+    let scope = match required_scope(&route) {
+        Some(scope) => scope,
+        None => {
+            let response = service.call(request);
+            return Box::pin(async move { Ok(response.await?.map_into_left_body()) });
+        }
+    };
+
+    let principal = request
+        .app_data::<web::Data<Authenticator>>()
+        .and_then(|authenticator| authenticator.authenticate(&request));
+
+    let principal = match principal {
+        Some(principal) => principal,
+        None => {
+            // Rejected requests are limited per address, beyond the limit they get a 429 instead
+            let decision = request
+                .app_data::<web::Data<RateLimiter>>()
+                .and_then(|limiter| limiter.acquire_unauthenticated(&request));
+            let response = match decision {
+                Some(decision) if !decision.allowed => {
+                    rate_limit::too_many_requests(&route, &decision)
+                }
+                _ => HttpResponse::Unauthorized()
+                    .insert_header((header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer")))
+                    .finish(),
+            };
+            return Box::pin(ready(Ok(request
+                .into_response(response)
+                .map_into_right_body())));
+        }
+    };
+
+    if let Some(root_span) = request.extensions().get::<RootSpan>() {
+        root_span.record("principal", principal.subject.as_str());
+    }
+
+    if !principal.has_scope(scope) {
+        tracing::warn!(
+            principal = %principal.subject,
+            scope,
+            "Principal lacks the scope of the route"
+        );
+        return Box::pin(ready(Ok(request
+            .into_response(HttpResponse::Forbidden().finish())
+            .map_into_right_body())));
+    }
+
+    request.extensions_mut().insert(principal);
+
+    let response = service.call(request);
+    Box::pin(async move { Ok(response.await?.map_into_left_body()) })
+}
+
+// Root span of the default builder with room for the authenticated principal
+pub struct AuthRootSpanBuilder;
+
+impl RootSpanBuilder for AuthRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> tracing::Span {
+        tracing_actix_web::root_span!(request, principal = tracing::field::Empty)
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: tracing::Span,
+        outcome: &Result<ServiceResponse<B>, Error>,
+    ) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
@@ -1,37 +1,426 @@
+use std::path::{Path, PathBuf};
+
 use config::Config;
+use secrecy::Secret;
 use serde::Deserialize;
+use transactions_store::{
+    connection::{connection_string, DatabaseTlsSettings},
+    layout::CollectionLayout,
+};
 
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize)]
 pub struct Settings {
     pub database: DatabaseSettings,
-    pub application_port: u16,
+    pub application: ApplicationSettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    #[serde(default)]
+    pub graphql: GraphQLSettings,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApplicationSettings {
+    pub host: String,
+    pub port: u16,
+    // Serves HTTPS instead of plain HTTP when set
+    pub tls: Option<TlsSettings>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TlsSettings {
+    // PEM encoded certificate chain and private key, reloaded when either file changes
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    #[serde(default = "default_reload_interval_secs")]
+    pub reload_interval_secs: u64,
+}
+
+fn default_reload_interval_secs() -> u64 {
+    60
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize)]
 pub struct DatabaseSettings {
     pub username: String,
-    pub password: String,
+    // Either of `password` and `password_file` is required
+    pub password: Option<Secret<String>>,
+    // File holding the password, e.g. a docker or kubernetes secret mount
+    pub password_file: Option<PathBuf>,
     pub port: u16,
     pub host: String,
     pub bucket_name: String,
     pub scope_name: String,
     pub collection_name: String,
-    pub test_bucket_name: String,
-    pub test_scope_name: String,
-    pub test_collection_name: String,
+    #[serde(default)]
+    pub layout: CollectionLayout,
+    pub tls: Option<DatabaseTlsSettings>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuthSettings {
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeySettings>,
+    pub jwt: Option<JwtSettings>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiKeySettings {
+    // Either of `key` and `key_file` is required, the file takes precedence
+    pub key: Option<Secret<String>>,
+    pub key_file: Option<PathBuf>,
+    pub subject: String,
+    pub scopes: Vec<String>,
+}
+
+// Tokens are verified with either a shared HMAC secret or the public keys of a JWKS file
+#[derive(Debug, Deserialize)]
+pub struct JwtSettings {
+    pub hmac_secret: Option<Secret<String>>,
+    // File holding the HMAC secret, takes precedence over `hmac_secret`
+    pub hmac_secret_file: Option<PathBuf>,
+    pub jwks_file: Option<PathBuf>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RateLimitSettings {
+    // Applies to routes without a limit of their own, no limit when missing
+    pub default: Option<LimitSettings>,
+    #[serde(default)]
+    pub routes: Vec<RouteLimitSettings>,
+    // Requests turned away for missing or unknown credentials, per address across all routes.
+    // Falls back to the default.
+    pub unauthenticated: Option<LimitSettings>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RouteLimitSettings {
+    // Route pattern, e.g. /transactions/{type}
+    pub route: String,
+    pub limit: LimitSettings,
+}
+
+// Token bucket allowing bursts of `capacity` requests, refilled by `per_second` tokens
+#[derive(Debug, Deserialize)]
+pub struct LimitSettings {
+    pub capacity: u32,
+    pub per_second: f64,
+}
+
+// Queries beyond either limit are rejected before any resolver runs
+#[derive(Debug, Deserialize)]
+pub struct GraphQLSettings {
+    pub max_depth: usize,
+    // Fields count 1, connections multiply their selection by `first`
+    pub max_complexity: usize,
+}
+
+impl Default for GraphQLSettings {
+    fn default() -> Self {
+        GraphQLSettings {
+            max_depth: 8,
+            max_complexity: 1000,
+        }
+    }
 }
 
 impl DatabaseSettings {
     pub fn connection_string(&self) -> String {
-        format!("couchbase://{}:{}", self.host, self.port)
+        connection_string(&self.host, self.port, self.tls.as_ref())
+    }
+}
+
+pub enum Environment {
+    Local,
+    Production,
+    // Integration tests, points at the test bucket
+    Test,
+}
+
+impl Environment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Environment::Local => "local",
+            Environment::Production => "production",
+            Environment::Test => "test",
+        }
+    }
+}
+
+impl TryFrom<String> for Environment {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "production" => Ok(Self::Production),
+            "test" => Ok(Self::Test),
+            other => Err(format!(
+                "{} is not a supported environment. Use either `local`, `production` or `test`.",
+                other
+            )),
+        }
     }
 }
 
+// Layers configuration/base.yml, the overlay of APP_ENVIRONMENT (local by default)
+// and APP_ prefixed environment variables, e.g. APP_DATABASE__HOST
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+    let environment: Environment = std::env::var("APP_ENVIRONMENT")
+        .unwrap_or_else(|_| "local".into())
+        .try_into()
+        .map_err(config::ConfigError::Message)?;
+
+    get_configuration_for(environment)
+}
+
+pub fn get_configuration_for(environment: Environment) -> Result<Settings, config::ConfigError> {
+    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
+
+    load_configuration(&base_path.join("configuration"), environment, None)
+}
+
+// `variables` replace the process environment, tests pass their own
+fn load_configuration(
+    configuration_directory: &Path,
+    environment: Environment,
+    variables: Option<config::Map<String, String>>,
+) -> Result<Settings, config::ConfigError> {
     let settings = Config::builder()
-        .add_source(config::File::with_name("configuration"))
-        .build()
+        .add_source(config::File::from(configuration_directory.join("base.yml")))
+        .add_source(config::File::from(
+            configuration_directory.join(format!("{}.yml", environment.as_str())),
+        ))
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .prefix_separator("_")
+                .separator("__")
+                .try_parsing(true)
+                .source(variables),
+        )
+        .build()?;
+
+    let mut settings: Settings = settings.try_deserialize()?;
+
+    match &settings.database.password_file {
+        Some(password_file) => settings.database.password = Some(read_secret(password_file)?),
+        None if settings.database.password.is_none() => {
+            return Err(config::ConfigError::Message(
+                "database requires password or password_file".into(),
+            ))
+        }
+        None => {}
+    }
+
+    for api_key in &mut settings.auth.api_keys {
+        if let Some(key_file) = &api_key.key_file {
+            api_key.key = Some(read_secret(key_file)?);
+        }
+    }
+
+    if let Some(jwt) = &mut settings.auth.jwt {
+        if let Some(hmac_secret_file) = &jwt.hmac_secret_file {
+            jwt.hmac_secret = Some(read_secret(hmac_secret_file)?);
+        }
+    }
+
+    Ok(settings)
+}
+
+fn read_secret(path: &Path) -> Result<Secret<String>, config::ConfigError> {
+    std::fs::read_to_string(path)
+        .map(|secret| Secret::new(secret.trim_end().to_string()))
+        .map_err(|e| {
+            config::ConfigError::Message(format!(
+                "Failed to read secret from {}: {}",
+                path.display(),
+                e
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::ExposeSecret;
+
+    use super::*;
+
+    fn load(variables: &[(&str, &str)]) -> Result<Settings, config::ConfigError> {
+        load_from(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("configuration"),
+            variables,
+        )
+    }
+
+    fn load_from(
+        configuration_directory: &Path,
+        variables: &[(&str, &str)],
+    ) -> Result<Settings, config::ConfigError> {
+        let variables = variables
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        load_configuration(configuration_directory, Environment::Local, Some(variables))
+    }
+
+    // base.yml of the repository overlaid with the application host and `local`, for settings
+    // variables cannot reach such as list items
+    fn configuration_with_local(name: &str, local: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("configuration/base.yml"),
+            directory.join("base.yml"),
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("local.yml"),
+            format!("application:\n  host: 127.0.0.1\n{}", local),
+        )
+        .unwrap();
+        directory
+    }
+
+    #[test]
+    fn environment_overlay_extends_and_replaces_base() {
+        let settings = load(&[]).unwrap();
+
+        // Only in base.yml
+        assert_eq!(8080, settings.application.port);
+        assert_eq!("127.0.0.1", settings.database.host);
+        // Only in local.yml
+        assert_eq!("127.0.0.1", settings.application.host);
+        // Empty in base.yml, replaced by local.yml
+        assert_eq!(1, settings.auth.api_keys.len());
+        assert_eq!("local-developer", settings.auth.api_keys[0].subject);
+    }
+
+    #[test]
+    fn app_variables_override_files() {
+        let settings = load(&[
+            ("APP_APPLICATION__PORT", "9090"),
+            ("APP_DATABASE__HOST", "couchbase.internal"),
+            ("DATABASE__HOST", "ignored-without-prefix"),
+        ])
+        .unwrap();
+
+        assert_eq!(9090, settings.application.port);
+        assert_eq!("couchbase.internal", settings.database.host);
+        assert_eq!(
+            "password",
+            settings.database.password.unwrap().expose_secret()
+        );
+    }
+
+    fn secret_file(name: &str, secret: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+        std::fs::write(&path, secret).unwrap();
+        path
+    }
+
+    #[test]
+    fn password_file_takes_precedence_over_password() {
+        let password_file = secret_file("database-password", "from-file\n");
+
+        let settings = load(&[
+            ("APP_DATABASE__PASSWORD", "from-variable"),
+            (
+                "APP_DATABASE__PASSWORD_FILE",
+                password_file.to_str().unwrap(),
+            ),
+        ])
         .unwrap();
 
-    settings.try_deserialize()
+        assert_eq!(
+            "from-file",
+            settings.database.password.unwrap().expose_secret()
+        );
+    }
+
+    #[test]
+    fn missing_password_file_is_rejected() {
+        let result = load(&[(
+            "APP_DATABASE__PASSWORD_FILE",
+            "/nonexistent/database-password",
+        )]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn key_file_takes_precedence_over_key() {
+        let key_file = secret_file("api-key", "key-from-file\n");
+        let directory = configuration_with_local(
+            "key-file-configuration",
+            &format!(
+                "auth:\n  api_keys:\n    - key: \"from-configuration\"\n      key_file: \"{}\"\n      \
+                subject: \"test\"\n      scopes: []\n",
+                key_file.display()
+            ),
+        );
+
+        let settings = load_from(&directory, &[("APP_DATABASE__PASSWORD", "password")]).unwrap();
+
+        assert_eq!(
+            "key-from-file",
+            settings.auth.api_keys[0]
+                .key
+                .as_ref()
+                .unwrap()
+                .expose_secret()
+        );
+    }
+
+    #[test]
+    fn hmac_secret_file_takes_precedence_over_hmac_secret() {
+        let hmac_secret_file = secret_file("hmac-secret", "secret-from-file\n");
+
+        let settings = load(&[
+            ("APP_AUTH__JWT__HMAC_SECRET", "from-variable"),
+            (
+                "APP_AUTH__JWT__HMAC_SECRET_FILE",
+                hmac_secret_file.to_str().unwrap(),
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            "secret-from-file",
+            settings
+                .auth
+                .jwt
+                .unwrap()
+                .hmac_secret
+                .unwrap()
+                .expose_secret()
+        );
+    }
+
+    #[test]
+    fn missing_key_and_hmac_secret_files_are_rejected() {
+        let directory = configuration_with_local(
+            "missing-key-file-configuration",
+            "auth:\n  api_keys:\n    - key_file: \"/nonexistent/api-key\"\n      \
+            subject: \"test\"\n      scopes: []\n",
+        );
+
+        assert!(load_from(&directory, &[("APP_DATABASE__PASSWORD", "password")]).is_err());
+        assert!(load(&[(
+            "APP_AUTH__JWT__HMAC_SECRET_FILE",
+            "/nonexistent/hmac-secret"
+        )])
+        .is_err());
+    }
+
+    #[test]
+    fn database_requires_password_or_password_file() {
+        let directory = configuration_with_local("no-password-configuration", "");
+
+        assert!(load_from(&directory, &[]).is_err());
+        assert!(load_from(&directory, &[("APP_DATABASE__PASSWORD", "password")]).is_ok());
+    }
 }
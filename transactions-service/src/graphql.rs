@@ -0,0 +1,299 @@
+use async_graphql::{
+    connection::{Connection, Edge},
+    Context, EmptyMutation, EmptySubscription, Enum, Error, InputObject, Object, Result, Schema,
+    ID,
+};
+use chrono::DateTime;
+use couchbase::CouchbaseError;
+
+use crate::{
+    auth::Access,
+    configuration::GraphQLSettings,
+    model::{
+        self, PageCursor, TransactionFilter, TransactionType, UserBalance, DEFAULT_PAGE_SIZE,
+        MAX_PAGE_SIZE,
+    },
+    repository, CouchbaseConnection,
+};
+
+pub type TransactionsSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+// The `Access` of the caller is added to the data of every request by the route
+pub fn build_schema(
+    connection_data: CouchbaseConnection,
+    settings: &GraphQLSettings,
+) -> TransactionsSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(connection_data)
+        .limit_depth(settings.max_depth)
+        .limit_complexity(settings.max_complexity)
+        .finish()
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "crate::model::StatsGrouping")]
+pub enum StatsGrouping {
+    User,
+    Hour,
+    Day,
+    Month,
+}
+
+#[derive(InputObject, Debug)]
+pub struct AmountRange {
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+pub struct Transaction(model::Transaction);
+
+#[Object]
+impl Transaction {
+    async fn id(&self) -> ID {
+        ID::from(self.0.id)
+    }
+
+    async fn user_id(&self) -> ID {
+        ID::from(self.0.user_id)
+    }
+
+    async fn amount(&self) -> f64 {
+        self.0.amount
+    }
+
+    #[graphql(name = "type")]
+    async fn transaction_type(&self) -> TransactionType {
+        self.0.transaction_type
+    }
+
+    // RFC 3339, missing for documents without a timestamp
+    async fn timestamp(&self) -> Option<String> {
+        self.0.timestamp.and_then(|timestamp| {
+            DateTime::from_timestamp(
+                (timestamp / 1000) as i64,
+                (timestamp % 1000) as u32 * 1_000_000,
+            )
+            .map(|timestamp| timestamp.to_rfc3339())
+        })
+    }
+
+    async fn user(&self) -> User {
+        User { id: self.0.user_id }
+    }
+}
+
+pub struct User {
+    id: u64,
+}
+
+#[Object]
+impl User {
+    async fn id(&self) -> ID {
+        ID::from(self.id)
+    }
+
+    // Deposits, refunds and bonuses minus every other type
+    #[graphql(complexity = "QUERY_COMPLEXITY")]
+    async fn balance(&self, ctx: &Context<'_>) -> Result<f64> {
+        let totals = repository::user_totals(connection_data(ctx), access(ctx), self.id)
+            .await
+            .map_err(query_error)?;
+
+        Ok(UserBalance::from_totals(self.id, totals).balance)
+    }
+
+    #[graphql(complexity = "page_complexity(first, child_complexity)")]
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Transaction>> {
+        let filter = TransactionFilter {
+            user_id: Some(self.id),
+            ..TransactionFilter::default()
+        };
+
+        transactions_page(ctx, &filter, first, after).await
+    }
+}
+
+pub struct TransactionStats(model::TransactionStats);
+
+#[Object]
+impl TransactionStats {
+    #[graphql(name = "type")]
+    async fn transaction_type(&self) -> TransactionType {
+        self.0.transaction_type
+    }
+
+    async fn user_id(&self) -> Option<ID> {
+        self.0.user_id.map(ID::from)
+    }
+
+    async fn bucket(&self) -> Option<&str> {
+        self.0.bucket.as_deref()
+    }
+
+    async fn count(&self) -> u64 {
+        self.0.count
+    }
+
+    async fn sum(&self) -> f64 {
+        self.0.sum
+    }
+
+    async fn min(&self) -> f64 {
+        self.0.min
+    }
+
+    async fn max(&self) -> f64 {
+        self.0.max
+    }
+
+    async fn avg(&self) -> f64 {
+        self.0.avg
+    }
+
+    async fn p50(&self) -> f64 {
+        self.0.p50
+    }
+
+    async fn p90(&self) -> f64 {
+        self.0.p90
+    }
+
+    async fn p95(&self) -> f64 {
+        self.0.p95
+    }
+
+    async fn p99(&self) -> f64 {
+        self.0.p99
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    // Ordered by id, `after` takes the cursor of the last edge of the previous page
+    #[graphql(complexity = "page_complexity(first, child_complexity)")]
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(name = "type")] transaction_type: Option<TransactionType>,
+        user_id: Option<ID>,
+        amount_range: Option<AmountRange>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Transaction>> {
+        let filter = TransactionFilter {
+            transaction_type,
+            user_id: user_id.as_ref().map(parse_id).transpose()?,
+            min_amount: amount_range.as_ref().and_then(|range| range.min),
+            max_amount: amount_range.as_ref().and_then(|range| range.max),
+        };
+
+        transactions_page(ctx, &filter, first, after).await
+    }
+
+    #[graphql(complexity = "QUERY_COMPLEXITY + child_complexity")]
+    async fn transaction(&self, ctx: &Context<'_>, id: ID) -> Result<Option<Transaction>> {
+        let transaction =
+            repository::transaction(connection_data(ctx), access(ctx), parse_id(&id)?)
+                .await
+                .map_err(query_error)?;
+
+        Ok(transaction.map(Transaction))
+    }
+
+    // Fields of the user are only queried when selected
+    async fn user(&self, id: ID) -> Result<User> {
+        Ok(User { id: parse_id(&id)? })
+    }
+
+    #[graphql(complexity = "QUERY_COMPLEXITY + child_complexity")]
+    async fn stats(
+        &self,
+        ctx: &Context<'_>,
+        group_by: Option<StatsGrouping>,
+    ) -> Result<Vec<TransactionStats>> {
+        let stats = repository::transaction_stats(
+            connection_data(ctx),
+            access(ctx),
+            group_by.map(Into::into),
+        )
+        .await
+        .map_err(query_error)?;
+
+        Ok(stats.into_iter().map(TransactionStats).collect())
+    }
+}
+
+async fn transactions_page(
+    ctx: &Context<'_>,
+    filter: &TransactionFilter,
+    first: Option<i32>,
+    after: Option<String>,
+) -> Result<Connection<String, Transaction>> {
+    let limit = match first {
+        None => DEFAULT_PAGE_SIZE,
+        Some(first) if first > 0 && first as u32 <= MAX_PAGE_SIZE => first as u32,
+        Some(_) => return Err(format!("first has to be 1 to {}", MAX_PAGE_SIZE).into()),
+    };
+    let after = after
+        .as_deref()
+        .map(str::parse::<PageCursor>)
+        .transpose()
+        .map_err(Error::new)?;
+
+    let page = repository::transactions_page(
+        connection_data(ctx),
+        access(ctx),
+        filter,
+        limit,
+        after.as_ref(),
+    )
+    .await
+    .map_err(query_error)?;
+
+    let mut connection = Connection::new(after.is_some(), page.len() == limit as usize);
+    connection.edges.extend(
+        page.into_iter()
+            .map(|paged| Edge::new(paged.cursor().encode(), Transaction(paged.transaction))),
+    );
+
+    Ok(connection)
+}
+
+// Fields running a Couchbase query cost this much, so selecting them for every node of a page
+// (e.g. the balance of the user of each transaction) exceeds the complexity limit
+const QUERY_COMPLEXITY: usize = 100;
+
+// A page runs one query and multiplies the complexity of its selection by the number of
+// transactions it may hold
+fn page_complexity(first: Option<i32>, child_complexity: usize) -> usize {
+    let first = first.map_or(DEFAULT_PAGE_SIZE as usize, |first| first.max(0) as usize);
+    first
+        .saturating_mul(child_complexity)
+        .saturating_add(QUERY_COMPLEXITY)
+}
+
+fn connection_data<'a>(ctx: &Context<'a>) -> &'a CouchbaseConnection {
+    ctx.data_unchecked::<CouchbaseConnection>()
+}
+
+// Requests without an `Access`, e.g. from tests executing the schema directly, see nothing
+fn access(ctx: &Context<'_>) -> Access {
+    ctx.data_opt::<Access>().copied().unwrap_or(Access::Nothing)
+}
+
+fn parse_id(id: &ID) -> Result<u64> {
+    id.parse::<u64>()
+        .map_err(|_| format!("{} is not a valid id", id.as_str()).into())
+}
+
+fn query_error(e: CouchbaseError) -> Error {
+    tracing::error!("Query error: {}", e);
+    Error::new("Query error")
+}
@@ -1,18 +1,36 @@
 use std::{net::TcpListener, sync::Arc};
 
 use actix_web::{dev::Server, web, App, HttpServer};
-use configuration::Settings;
+use auth::{AuthRootSpanBuilder, Authenticator};
+use configuration::DatabaseSettings;
 use couchbase::Cluster;
+use graphql::TransactionsSchema;
+use rate_limit::RateLimiter;
 use routes::{
-    health_check::hello,
-    transactions::{transactions, transactions_by_type},
+    graphql::graphql_query,
+    health_check::{hello, liveness, readiness},
+    metrics::prometheus_metrics,
+    openapi::openapi_spec,
+    v1, v2,
 };
+use rustls::ServerConfig;
+use secrecy::ExposeSecret;
 use tracing_actix_web::TracingLogger;
+use transactions_store::layout::CollectionLayout;
 
+pub mod archive;
+pub mod auth;
 pub mod configuration;
+pub mod graphql;
+pub mod metrics;
 pub mod model;
+pub mod openapi;
+pub mod rate_limit;
+pub mod repository;
 pub mod routes;
 pub mod telemetry;
+pub mod tls;
+pub mod versioning;
 
 #[derive(Debug, Clone)]
 pub struct CouchbaseConnection {
@@ -20,36 +38,27 @@ pub struct CouchbaseConnection {
     pub bucket_name: String,
     pub scope_name: String,
     pub collection_name: String,
+    pub layout: CollectionLayout,
 }
 
 impl CouchbaseConnection {
-    pub fn new(configuration: &Settings) -> Self {
+    pub fn new(settings: &DatabaseSettings) -> Self {
         let cluster = Cluster::connect(
-            &configuration.database.connection_string(),
-            &configuration.database.username,
-            &configuration.database.password,
+            &settings.connection_string(),
+            &settings.username,
+            settings
+                .password
+                .as_ref()
+                .map(|password| password.expose_secret().as_str())
+                .unwrap_or_default(),
         );
 
         CouchbaseConnection {
             cluster: Arc::new(cluster),
-            bucket_name: configuration.database.bucket_name.clone(),
-            scope_name: configuration.database.scope_name.clone(),
-            collection_name: configuration.database.collection_name.clone(),
-        }
-    }
-
-    pub fn test_connection(configuration: &Settings) -> Self {
-        let cluster = Cluster::connect(
-            &configuration.database.connection_string(),
-            &configuration.database.username,
-            &configuration.database.password,
-        );
-
-        CouchbaseConnection {
-            cluster: Arc::new(cluster),
-            bucket_name: configuration.database.test_bucket_name.clone(),
-            scope_name: configuration.database.test_scope_name.clone(),
-            collection_name: configuration.database.test_collection_name.clone(),
+            bucket_name: settings.bucket_name.clone(),
+            scope_name: settings.scope_name.clone(),
+            collection_name: settings.collection_name.clone(),
+            layout: settings.layout.clone(),
         }
     }
 }
@@ -57,19 +66,49 @@ impl CouchbaseConnection {
 pub async fn run(
     listener: TcpListener,
     connection_data: CouchbaseConnection,
+    authenticator: Authenticator,
+    rate_limiter: RateLimiter,
+    schema: TransactionsSchema,
+    tls: Option<ServerConfig>,
 ) -> Result<Server, std::io::Error> {
     let connection_data = web::Data::new(connection_data);
+    let authenticator = web::Data::new(authenticator);
+    // Shared by the workers so limits hold across all of them
+    let rate_limiter = web::Data::new(rate_limiter);
+    let schema = web::Data::new(schema);
 
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(TracingLogger::default())
-            .service(transactions)
-            .service(transactions_by_type)
+            // Runs inside `auth::authorize`, which adds the principal the buckets are keyed by
+            .wrap_fn(rate_limit::limit)
+            .wrap_fn(auth::authorize)
+            .wrap_fn(metrics::track_request)
+            .wrap(TracingLogger::<AuthRootSpanBuilder>::new())
+            .service(web::scope("/v1").configure(v1::configure))
+            .service(web::scope("/v2").configure(v2::configure))
             .service(hello)
+            .service(liveness)
+            .service(readiness)
+            .service(prometheus_metrics)
+            .service(openapi_spec)
+            .service(graphql_query)
+            .configure(openapi::swagger_ui)
+            // Matches every path, so registered last
+            .service(
+                web::scope("")
+                    .wrap_fn(versioning::deprecated)
+                    .configure(v1::configure),
+            )
             .app_data(connection_data.clone())
-    })
-    .listen(listener)?
-    .run();
+            .app_data(authenticator.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(schema.clone())
+    });
+
+    let server = match tls {
+        Some(config) => server.listen_rustls_0_21(listener, config)?,
+        None => server.listen(listener)?,
+    };
 
-    Ok(server)
+    Ok(server.run())
 }
@@ -1,26 +1,139 @@
-use std::net::TcpListener;
+use std::{
+    net::TcpListener,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
 use transactions_service::{
+    archive::archive_transactions,
+    auth::Authenticator,
     configuration::get_configuration,
+    graphql::build_schema,
+    model::TransactionType,
+    rate_limit::RateLimiter,
     run,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, get_tracer, init_subscriber, shutdown_tracer},
+    tls::server_config,
     CouchbaseConnection,
 };
+use transactions_store::{
+    migrations::run_migrations,
+    provisioning::{provision, Schema},
+};
+
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Provision the Couchbase schema and run pending migrations
+    Migrate,
+    /// Move transactions older than a date to partitioned Parquet files
+    Archive {
+        /// Transactions before midnight UTC of this date are archived (YYYY-MM-DD)
+        #[arg(long)]
+        before: NaiveDate,
+        #[arg(long, default_value = "archive")]
+        output: PathBuf,
+        /// Remove archived documents once row counts are verified
+        #[arg(long)]
+        delete: bool,
+    },
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let tracer = get_tracer("transactions-service".into());
     let subscriber = get_subscriber(
         "transactions-service".into(),
         "info".into(),
         std::io::stdout,
+        tracer,
     );
     init_subscriber(subscriber);
 
     let configuration = get_configuration().expect("Failed to read configuration.");
-    let connection_data = CouchbaseConnection::new(&configuration);
+    let connection_data = CouchbaseConnection::new(&configuration.database);
 
-    let address = format!("127.0.0.1:{}", configuration.application_port);
+    match Cli::parse().command {
+        Some(Command::Migrate) => {
+            migrate(&connection_data).await;
+            return Ok(());
+        }
+        Some(Command::Archive {
+            before,
+            output,
+            delete,
+        }) => {
+            let summary = archive_transactions(&connection_data, before, &output, delete)
+                .await
+                .expect("Archiving transactions failed");
+            tracing::info!(
+                files = summary.files.len(),
+                archived = summary.archived,
+                deleted = summary.deleted,
+                "Archive finished"
+            );
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let address = format!(
+        "{}:{}",
+        configuration.application.host, configuration.application.port
+    );
     let listener = TcpListener::bind(address)?;
-    let server = run(listener, connection_data).await?;
-    server.await
+    let authenticator =
+        Authenticator::new(&configuration.auth).expect("Invalid auth configuration.");
+    let rate_limiter =
+        RateLimiter::new(&configuration.rate_limit).expect("Invalid rate limit configuration.");
+    let schema = build_schema(connection_data.clone(), &configuration.graphql);
+    let tls = configuration
+        .application
+        .tls
+        .as_ref()
+        .map(|tls| server_config(tls).expect("Invalid TLS configuration."));
+    let server = run(
+        listener,
+        connection_data,
+        authenticator,
+        rate_limiter,
+        schema,
+        tls,
+    )
+    .await?;
+    server.await?;
+
+    shutdown_tracer();
+    Ok(())
+}
+
+// Run with APP_ENVIRONMENT=test to provision the bucket of the integration tests
+async fn migrate(connection_data: &CouchbaseConnection) {
+    let schema = Schema {
+        bucket_name: connection_data.bucket_name.clone(),
+        scope_name: connection_data.scope_name.clone(),
+        collections: connection_data.layout.initial_collections(
+            &TransactionType::ALL
+                .iter()
+                .map(|transaction_type| transaction_type.collection_name().to_string())
+                .collect::<Vec<_>>(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("System time is before unix epoch")
+                .as_millis() as u64,
+        ),
+    };
+    provision(&connection_data.cluster, &schema)
+        .await
+        .expect("Schema provisioning failed");
+    run_migrations(&connection_data.cluster, &schema)
+        .await
+        .expect("Schema migration failed");
 }
@@ -0,0 +1,144 @@
+use std::{future::Future, time::Instant};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse},
+    Error,
+};
+use couchbase::CouchbaseResult;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+// Every app spawned in the process shares the registry, labels tell the routes apart
+static REGISTRY: Lazy<Registry> = Lazy::new(|| {
+    Registry::new_custom(Some("transactions_service".to_string()), None)
+        .expect("Metrics registry creation failed")
+});
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("http_requests_total", "HTTP requests by route and status"),
+        &["method", "route", "status"],
+    ))
+});
+
+static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+        &["method", "route"],
+    ))
+});
+
+static HTTP_REQUESTS_IN_FLIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "http_requests_in_flight",
+        "HTTP requests currently being served",
+    ))
+});
+
+static DB_QUERY_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("db_query_duration_seconds", "Couchbase query latency"),
+        &["kind"],
+    ))
+});
+
+static DB_QUERY_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("db_query_errors_total", "Failed couchbase queries"),
+        &["kind"],
+    ))
+});
+
+fn register<T>(metric: prometheus::Result<T>) -> T
+where
+    T: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("Invalid metric definition");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric registration failed");
+    metric
+}
+
+// Used with `App::wrap_fn`, routes are labelled by their pattern so path parameters
+// like user ids do not create a series each
+pub fn track_request<S, B>(
+    request: ServiceRequest,
+    service: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let method = request.method().to_string();
+    let route = request
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let in_flight = InFlight::start();
+    let start = Instant::now();
+    let response = service.call(request);
+
+    async move {
+        let response = response.await;
+
+        drop(in_flight);
+        HTTP_REQUEST_DURATION
+            .with_label_values(&[&method, &route])
+            .observe(start.elapsed().as_secs_f64());
+
+        let status = match &response {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        HTTP_REQUESTS
+            .with_label_values(&[&method, &route, status.as_str()])
+            .inc();
+
+        response
+    }
+}
+
+// Requests dropped before completing, e.g. by a client disconnecting, leave the gauge too
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        HTTP_REQUESTS_IN_FLIGHT.inc();
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        HTTP_REQUESTS_IN_FLIGHT.dec();
+    }
+}
+
+pub async fn observe_query<T>(
+    kind: &str,
+    query: impl Future<Output = CouchbaseResult<T>>,
+) -> CouchbaseResult<T> {
+    let start = Instant::now();
+    let result = query.await;
+
+    DB_QUERY_DURATION
+        .with_label_values(&[kind])
+        .observe(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        DB_QUERY_ERRORS.with_label_values(&[kind]).inc();
+    }
+
+    result
+}
+
+pub fn encode() -> Result<(String, Vec<u8>), prometheus::Error> {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&REGISTRY.gather(), &mut buffer)?;
+
+    Ok((encoder.format_type().to_string(), buffer))
+}
@@ -1,6 +1,10 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
 
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub use transactions_store::model::TransactionType;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CouchbaseTransactionWrapper {
@@ -8,18 +12,209 @@ pub struct CouchbaseTransactionWrapper {
     pub inner: HashMap<String, Transaction>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Transaction {
     pub id: u64,
     pub user_id: u64,
     pub amount: f64,
     pub transaction_type: TransactionType,
+    // Milliseconds since epoch, backfilled by migrations for older documents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TransactionTotal {
+    pub transaction_type: TransactionType,
+    pub count: u64,
+    pub total: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UserBalance {
+    pub user_id: u64,
+    pub balance: f64,
+    pub totals: Vec<TransactionTotal>,
+}
+
+impl UserBalance {
+    pub fn from_totals(user_id: u64, totals: Vec<TransactionTotal>) -> Self {
+        let balance = totals
+            .iter()
+            .map(|total| total.transaction_type.balance_sign() * total.total)
+            .sum();
+
+        UserBalance {
+            user_id,
+            balance,
+            totals,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsGrouping {
+    User,
+    Hour,
+    Day,
+    Month,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TransactionStats {
+    pub transaction_type: TransactionType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+// Page sizes of the paginated APIs
+pub const DEFAULT_PAGE_SIZE: u32 = 100;
+pub const MAX_PAGE_SIZE: u32 = 1000;
+
+// Response envelope of the v2 API
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[aliases(TransactionPage = Page<Transaction>)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub pagination: Pagination,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Pagination {
+    pub limit: u32,
+    // Cursor of the next page, absent on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+// Position of a transaction in the id order of the pages. Ids may repeat across collections,
+// so the keyspace and key of its document break ties.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageCursor {
+    pub id: u64,
+    pub keyspace: String,
+    pub key: String,
+}
+
+impl PageCursor {
+    // Opaque to clients, URL safe base64 of the cursor's JSON
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("Failed to serialize cursor"))
+    }
 }
 
+impl FromStr for PageCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let json = URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(|_| "Invalid cursor".to_string())?;
+        serde_json::from_slice(&json).map_err(|_| "Invalid cursor".to_string())
+    }
+}
+
+// A transaction of a page together with the keyspace and key of its document
+#[derive(Debug, Clone, Deserialize)]
+pub struct PagedTransaction {
+    pub document_keyspace: String,
+    pub document_key: String,
+    #[serde(flatten)]
+    pub transaction: Transaction,
+}
+
+impl PagedTransaction {
+    pub fn cursor(&self) -> PageCursor {
+        PageCursor {
+            id: self.transaction.id,
+            keyspace: self.document_keyspace.clone(),
+            key: self.document_key.clone(),
+        }
+    }
+}
+
+// Narrows a page of transactions, unset fields match every transaction
+#[derive(Debug, Clone, Default)]
+pub struct TransactionFilter {
+    pub transaction_type: Option<TransactionType>,
+    pub user_id: Option<u64>,
+    pub min_amount: Option<f64>,
+    pub max_amount: Option<f64>,
+}
+
+// A transaction together with its document key
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum TransactionType {
-    Bet,
-    Trade,
-    Deposit,
-    Withdrawal,
+pub struct StoredTransaction {
+    pub id: String,
+    #[serde(flatten)]
+    pub transaction: Transaction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct HealthCheck {
+    pub name: String,
+    pub status: HealthStatus,
+    pub latency_ms: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    pub fn from_checks(checks: Vec<HealthCheck>) -> Self {
+        let status = if checks.iter().all(|check| check.status == HealthStatus::Up) {
+            HealthStatus::Up
+        } else {
+            HealthStatus::Down
+        };
+
+        HealthReport { status, checks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = PageCursor {
+            id: 42,
+            keyspace: "default:transactions.transactions.deposit".to_string(),
+            key: "42".to_string(),
+        };
+
+        assert_eq!(Ok(cursor.clone()), cursor.encode().parse::<PageCursor>());
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        for cursor in ["42", "not base64!", &URL_SAFE_NO_PAD.encode("[1, 2]")] {
+            assert!(cursor.parse::<PageCursor>().is_err(), "{}", cursor);
+        }
+    }
 }
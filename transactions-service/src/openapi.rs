@@ -0,0 +1,126 @@
+use actix_web::web;
+use utoipa::{
+    openapi::{
+        security::{
+            ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme,
+        },
+        OpenApi as OpenApiSpec, ResponseBuilder,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{
+    auth::{public_route, API_KEY_HEADER},
+    model::{
+        HealthCheck, HealthReport, HealthStatus, Pagination, StatsGrouping, Transaction,
+        TransactionPage, TransactionStats, TransactionTotal, TransactionType, UserBalance,
+    },
+    routes,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Transactions Service"),
+    paths(
+        routes::transactions::transactions,
+        routes::transactions::transactions_stats,
+        routes::export::transactions_export,
+        routes::transactions::transactions_by_type,
+        routes::users::user_transactions,
+        routes::users::user_balance,
+        routes::v2::transactions,
+        routes::health_check::hello,
+        routes::health_check::liveness,
+        routes::health_check::readiness,
+        routes::metrics::prometheus_metrics,
+        routes::openapi::openapi_spec,
+        routes::graphql::graphql_query,
+    ),
+    components(schemas(
+        Transaction,
+        TransactionType,
+        TransactionTotal,
+        UserBalance,
+        StatsGrouping,
+        TransactionStats,
+        TransactionPage,
+        Pagination,
+        HealthStatus,
+        HealthCheck,
+        HealthReport,
+    )),
+    modifiers(&Protection),
+    tags(
+        (name = "transactions", description = "Transactions across every type"),
+        (name = "users", description = "Transactions of a single user"),
+        (name = "graphql", description = "The queries of the transaction and user routes in one request"),
+        (name = "health", description = "Probes and metrics, no credentials needed"),
+        (name = "documentation", description = "This document, no credentials needed")
+    )
+)]
+pub struct ApiDoc;
+
+// Credentials and the responses of the authentication and rate limiting middlewares
+// apply to every route that is not public, handlers only document their own responses
+struct Protection;
+
+impl Modify for Protection {
+    fn modify(&self, openapi: &mut OpenApiSpec) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if public_route(path) {
+                continue;
+            }
+
+            for operation in item.operations.values_mut() {
+                operation.security = Some(vec![
+                    SecurityRequirement::new("api_key", Vec::<String>::new()),
+                    SecurityRequirement::new("bearer", Vec::<String>::new()),
+                ]);
+
+                let responses = &mut operation.responses.responses;
+                for (status, description) in [
+                    ("401", "Missing or invalid credentials"),
+                    ("403", "The credentials lack the scope of the route"),
+                    (
+                        "429",
+                        "Rate limit exceeded, retry after `Retry-After` seconds",
+                    ),
+                    ("500", "Query error"),
+                ] {
+                    responses.insert(
+                        status.to_string(),
+                        ResponseBuilder::new()
+                            .description(description)
+                            .build()
+                            .into(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "swagger-ui")]
+pub fn swagger_ui(config: &mut web::ServiceConfig) {
+    use utoipa_swagger_ui::{Config, SwaggerUi};
+
+    config.service(SwaggerUi::new("/swagger-ui/{_:.*}").config(Config::from("/openapi.json")));
+}
+
+#[cfg(not(feature = "swagger-ui"))]
+pub fn swagger_ui(_config: &mut web::ServiceConfig) {}
@@ -0,0 +1,397 @@
+use std::{
+    collections::HashMap,
+    future::{ready, Future},
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::Mutex,
+    time::Instant,
+};
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderMap, HeaderName, HeaderValue},
+    web, Error, HttpMessage, HttpResponse,
+};
+use lru::LruCache;
+
+use crate::{
+    auth::Principal,
+    configuration::{LimitSettings, RateLimitSettings},
+    versioning::unversioned,
+};
+
+pub const LIMIT_HEADER: &str = "x-ratelimit-limit";
+pub const REMAINING_HEADER: &str = "x-ratelimit-remaining";
+pub const RESET_HEADER: &str = "x-ratelimit-reset";
+
+// Buckets of the least recently seen clients are dropped beyond this many
+const MAX_BUCKETS: usize = 10_000;
+
+// Route of the bucket counting an address's requests without valid credentials
+const UNAUTHENTICATED: &str = "unauthenticated";
+
+#[derive(Debug, Clone, Copy)]
+struct Limit {
+    capacity: f64,
+    per_second: f64,
+}
+
+impl TryFrom<&LimitSettings> for Limit {
+    type Error = String;
+
+    fn try_from(settings: &LimitSettings) -> Result<Self, Self::Error> {
+        if settings.capacity == 0 || settings.per_second <= 0.0 {
+            return Err("Rate limits need a capacity and a refill rate above zero".into());
+        }
+
+        Ok(Limit {
+            capacity: settings.capacity as f64,
+            per_second: settings.per_second,
+        })
+    }
+}
+
+struct Bucket {
+    limit: Limit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.capacity);
+        self.updated = now;
+    }
+}
+
+#[derive(Debug)]
+pub struct Decision {
+    allowed: bool,
+    limit: u64,
+    remaining: u64,
+    // Seconds until the next token, respectively until the bucket is full again
+    retry_after: u64,
+    reset: u64,
+}
+
+impl Decision {
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(
+            HeaderName::from_static(LIMIT_HEADER),
+            HeaderValue::from(self.limit),
+        );
+        headers.insert(
+            HeaderName::from_static(REMAINING_HEADER),
+            HeaderValue::from(self.remaining),
+        );
+        headers.insert(
+            HeaderName::from_static(RESET_HEADER),
+            HeaderValue::from(self.reset),
+        );
+    }
+}
+
+// Token buckets per client and route, routes without a limit of their own use the default
+pub struct RateLimiter {
+    default: Option<Limit>,
+    routes: HashMap<String, Limit>,
+    unauthenticated: Option<Limit>,
+    buckets: Mutex<LruCache<(String, String), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings) -> Result<Self, String> {
+        let default = settings.default.as_ref().map(Limit::try_from).transpose()?;
+        let routes = settings
+            .routes
+            .iter()
+            .map(|route| {
+                Limit::try_from(&route.limit)
+                    .map(|limit| (route.route.clone(), limit))
+                    .map_err(|e| format!("{}: {}", route.route, e))
+            })
+            .collect::<Result<_, _>>()?;
+        let unauthenticated = settings
+            .unauthenticated
+            .as_ref()
+            .map(Limit::try_from)
+            .transpose()
+            .map_err(|e| format!("unauthenticated: {}", e))?;
+
+        Ok(RateLimiter {
+            default,
+            routes,
+            unauthenticated,
+            buckets: Mutex::new(LruCache::new(
+                NonZeroUsize::new(MAX_BUCKETS).expect("MAX_BUCKETS is above zero"),
+            )),
+        })
+    }
+
+    fn acquire(&self, client: String, route: &str) -> Option<Decision> {
+        // A versioned route with a limit of its own gets its own bucket, other versions of a route
+        // share the limit and bucket of its unversioned path
+        let route = if self.routes.contains_key(route) {
+            route
+        } else {
+            unversioned(route)
+        };
+        let limit = *self.routes.get(route).or(self.default.as_ref())?;
+
+        Some(self.take(client, route, limit))
+    }
+
+    // Counts a request rejected for its credentials, so keys and tokens cannot be guessed
+    // without limit. Such requests have no principal and are keyed by their address.
+    pub fn acquire_unauthenticated(&self, request: &ServiceRequest) -> Option<Decision> {
+        let limit = *self.unauthenticated.as_ref().or(self.default.as_ref())?;
+
+        Some(self.take(client_key(request), UNAUTHENTICATED, limit))
+    }
+
+    fn take(&self, client: String, route: &str, limit: Limit) -> Decision {
+        let now = Instant::now();
+
+        let mut buckets = self
+            .buckets
+            .lock()
+            .expect("Rate limit buckets are poisoned");
+
+        let bucket = buckets.get_or_insert_mut((client, route.to_string()), || Bucket {
+            limit,
+            tokens: limit.capacity,
+            updated: now,
+        });
+        bucket.refill(now);
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        Decision {
+            allowed,
+            limit: limit.capacity as u64,
+            remaining: bucket.tokens.floor() as u64,
+            retry_after: ((1.0 - bucket.tokens).max(0.0) / limit.per_second).ceil() as u64,
+            reset: ((limit.capacity - bucket.tokens) / limit.per_second).ceil() as u64,
+        }
+    }
+}
+
+// Authenticated callers are told apart by their subject, anonymous ones by the peer address
+fn client_key(request: &ServiceRequest) -> String {
+    if let Some(principal) = request.extensions().get::<Principal>() {
+        return format!("principal:{}", principal.subject);
+    }
+
+    match request.peer_addr() {
+        Some(address) => format!("ip:{}", address.ip()),
+        None => "unknown".to_string(),
+    }
+}
+
+pub fn too_many_requests(route: &str, decision: &Decision) -> HttpResponse {
+    tracing::warn!(
+        route = %route,
+        retry_after = decision.retry_after,
+        "Rate limit exceeded"
+    );
+
+    let mut response = HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, decision.retry_after))
+        .finish();
+    decision.insert_headers(response.headers_mut());
+
+    response
+}
+
+// Used with `App::wrap_fn` inside `auth::authorize`, so buckets belong to authenticated principals.
+// Requests with unknown credentials are counted by `auth::authorize` and never reach it.
+pub fn limit<S, B>(
+    request: ServiceRequest,
+    service: &S,
+) -> Pin<Box<dyn Future<Output = Result<ServiceResponse<EitherBody<B>>, Error>>>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let route = request
+        .match_pattern()
+        .unwrap_or_else(|| request.path().to_string());
+
+    let decision = request
+        .app_data::<web::Data<RateLimiter>>()
+        .and_then(|limiter| limiter.acquire(client_key(&request), &route));
+
+    let decision = match decision {
+        Some(decision) => decision,
+        None => {
+            let response = service.call(request);
+            return Box::pin(async move { Ok(response.await?.map_into_left_body()) });
+        }
+    };
+
+    if !decision.allowed {
+        let response = too_many_requests(&route, &decision);
+        return Box::pin(ready(Ok(request
+            .into_response(response)
+            .map_into_right_body())));
+    }
+
+    let response = service.call(request);
+    Box::pin(async move {
+        let mut response = response.await?;
+        decision.insert_headers(response.headers_mut());
+
+        Ok(response.map_into_left_body())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::RouteLimitSettings;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(&RateLimitSettings {
+            default: Some(LimitSettings {
+                capacity: 50,
+                per_second: 20.0,
+            }),
+            routes: vec![
+                RouteLimitSettings {
+                    route: "/transactions".to_string(),
+                    limit: LimitSettings {
+                        capacity: 2,
+                        per_second: 0.001,
+                    },
+                },
+                RouteLimitSettings {
+                    route: "/v2/transactions".to_string(),
+                    limit: LimitSettings {
+                        capacity: 20,
+                        per_second: 5.0,
+                    },
+                },
+            ],
+            unauthenticated: Some(LimitSettings {
+                capacity: 3,
+                per_second: 0.001,
+            }),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn clients_are_limited_per_route_across_versions() {
+        let limiter = limiter();
+
+        assert!(
+            limiter
+                .acquire("principal:a".into(), "/transactions")
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            limiter
+                .acquire("principal:a".into(), "/v1/transactions")
+                .unwrap()
+                .allowed
+        );
+        let limited = limiter
+            .acquire("principal:a".into(), "/transactions")
+            .unwrap();
+
+        assert!(!limited.allowed);
+        assert_eq!(0, limited.remaining);
+        assert!(limited.retry_after > 0);
+        assert!(
+            limiter
+                .acquire("principal:b".into(), "/transactions")
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            limiter
+                .acquire("principal:a".into(), "/health/live")
+                .unwrap()
+                .allowed
+        );
+    }
+
+    #[test]
+    fn versioned_routes_with_a_limit_have_their_own_bucket() {
+        let limiter = limiter();
+
+        for _ in 0..2 {
+            limiter.acquire("principal:a".into(), "/v1/transactions");
+        }
+        let paged = limiter
+            .acquire("principal:a".into(), "/v2/transactions")
+            .unwrap();
+
+        assert!(paged.allowed);
+        assert_eq!(20, paged.limit);
+        assert_eq!(19, paged.remaining);
+        assert!(
+            !limiter
+                .acquire("principal:a".into(), "/transactions")
+                .unwrap()
+                .allowed
+        );
+    }
+
+    #[test]
+    fn unauthenticated_requests_share_a_bucket_per_address() {
+        let limiter = limiter();
+        let request = |address: &str| {
+            actix_web::test::TestRequest::get()
+                .uri("/v1/transactions")
+                .peer_addr(address.parse().unwrap())
+                .to_srv_request()
+        };
+
+        for _ in 0..3 {
+            assert!(
+                limiter
+                    .acquire_unauthenticated(&request("10.0.0.1:4000"))
+                    .unwrap()
+                    .allowed
+            );
+        }
+        let limited = limiter
+            .acquire_unauthenticated(&request("10.0.0.1:4001"))
+            .unwrap();
+
+        assert!(!limited.allowed);
+        assert_eq!(3, limited.limit);
+        assert!(
+            limiter
+                .acquire_unauthenticated(&request("10.0.0.2:4000"))
+                .unwrap()
+                .allowed
+        );
+        // Authenticated requests of the address are limited apart
+        assert!(
+            limiter
+                .acquire("ip:10.0.0.1".into(), "/transactions")
+                .unwrap()
+                .allowed
+        );
+    }
+
+    #[test]
+    fn tracked_buckets_are_bounded() {
+        let limiter = limiter();
+
+        for client in 0..MAX_BUCKETS + 100 {
+            limiter.acquire(format!("ip:{}", client), "/transactions");
+        }
+
+        assert_eq!(MAX_BUCKETS, limiter.buckets.lock().unwrap().len());
+    }
+}
@@ -0,0 +1,576 @@
+use couchbase::{CouchbaseResult, QueryOptions, QueryResult};
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tracing::Instrument;
+
+use crate::{
+    auth::Access,
+    metrics::observe_query,
+    model::{
+        CouchbaseTransactionWrapper, PageCursor, PagedTransaction, StatsGrouping,
+        StoredTransaction, Transaction, TransactionFilter, TransactionStats, TransactionTotal,
+        TransactionType,
+    },
+    CouchbaseConnection,
+};
+use transactions_store::layout::Partition;
+
+// Keeps DELETE statements within a reasonable size
+const DELETE_CHUNK_SIZE: usize = 1000;
+
+pub async fn transactions(
+    connection_data: &CouchbaseConnection,
+    access: Access,
+    transaction_type: Option<TransactionType>,
+) -> CouchbaseResult<Vec<Transaction>> {
+    let query_span = tracing::info_span!(
+        "Fetching transactions from couchbase",
+        ?access,
+        ?transaction_type
+    );
+
+    let query = union_all(
+        connection_data,
+        transaction_type.as_ref(),
+        &access_conditions(access),
+        |keyspace, where_clause| format!("SELECT * FROM {} AS t{}", keyspace, where_clause),
+    )
+    .await?;
+
+    let wrappers: Vec<CouchbaseTransactionWrapper> = fetch_rows(
+        connection_data,
+        "transactions",
+        query,
+        query_options(access, json!({})),
+    )
+    .instrument(query_span)
+    .await?;
+
+    Ok(wrappers
+        .into_iter()
+        .flat_map(|wrapper| wrapper.inner.into_values())
+        .collect())
+}
+
+pub async fn user_transactions(
+    connection_data: &CouchbaseConnection,
+    access: Access,
+    user_id: u64,
+) -> CouchbaseResult<Vec<Transaction>> {
+    let query_span = tracing::info_span!(
+        "Fetching user transactions from couchbase",
+        ?access,
+        user_id
+    );
+
+    // Every branch hits the `user_id` secondary index of its collection
+    let mut conditions = vec!["t.user_id = $user_id"];
+    conditions.extend(access_conditions(access));
+    let query = union_all(
+        connection_data,
+        None,
+        &conditions,
+        |keyspace, where_clause| format!("SELECT * FROM {} AS t{}", keyspace, where_clause),
+    )
+    .await?;
+
+    let options = query_options(access, json!({ "user_id": user_id }));
+
+    let wrappers: Vec<CouchbaseTransactionWrapper> =
+        fetch_rows(connection_data, "user_transactions", query, options)
+            .instrument(query_span)
+            .await?;
+
+    Ok(wrappers
+        .into_iter()
+        .flat_map(|wrapper| wrapper.inner.into_values())
+        .collect())
+}
+
+pub async fn user_totals(
+    connection_data: &CouchbaseConnection,
+    access: Access,
+    user_id: u64,
+) -> CouchbaseResult<Vec<TransactionTotal>> {
+    let query_span =
+        tracing::info_span!("Summing user transactions in couchbase", ?access, user_id);
+
+    let mut conditions = vec!["t.user_id = $user_id"];
+    conditions.extend(access_conditions(access));
+    let union = union_all(
+        connection_data,
+        None,
+        &conditions,
+        |keyspace, where_clause| {
+            format!(
+                "SELECT t.transaction_type, t.amount FROM {} AS t{}",
+                keyspace, where_clause
+            )
+        },
+    )
+    .await?;
+    let query = format!(
+        "SELECT t.transaction_type, COUNT(*) AS count, SUM(t.amount) AS total \
+        FROM ({}) AS t \
+        GROUP BY t.transaction_type",
+        union
+    );
+
+    let options = query_options(access, json!({ "user_id": user_id }));
+
+    fetch_rows(connection_data, "user_totals", query, options)
+        .instrument(query_span)
+        .await
+}
+
+pub async fn transaction_stats(
+    connection_data: &CouchbaseConnection,
+    access: Access,
+    group_by: Option<StatsGrouping>,
+) -> CouchbaseResult<Vec<TransactionStats>> {
+    let query_span =
+        tracing::info_span!("Aggregating transactions in couchbase", ?access, ?group_by);
+
+    let (group_projection, group_key) = match group_by {
+        None => (String::new(), String::new()),
+        Some(StatsGrouping::User) => (", t.user_id".to_string(), ", t.user_id".to_string()),
+        Some(grouping) => {
+            let date_part = match grouping {
+                StatsGrouping::Hour => "hour",
+                StatsGrouping::Day => "day",
+                _ => "month",
+            };
+            let expression = format!(
+                "DATE_TRUNC_STR(MILLIS_TO_UTC(t.timestamp), \"{}\")",
+                date_part
+            );
+            (
+                format!(", {} AS bucket", expression),
+                format!(", {}", expression),
+            )
+        }
+    };
+
+    let conditions = access_conditions(access);
+    let union = union_all(
+        connection_data,
+        None,
+        &conditions,
+        |keyspace, where_clause| {
+            format!(
+                "SELECT t.transaction_type, t.user_id, t.amount, t.timestamp FROM {} AS t{}",
+                keyspace, where_clause
+            )
+        },
+    )
+    .await?;
+
+    // Percentiles use the nearest-rank method over the sorted amounts of a group
+    let query = format!(
+        "SELECT t.transaction_type{projection}, \
+        COUNT(*) AS count, \
+        SUM(t.amount) AS sum, \
+        MIN(t.amount) AS min, \
+        MAX(t.amount) AS max, \
+        AVG(t.amount) AS avg, \
+        amounts[CEIL(0.50 * COUNT(*)) - 1] AS p50, \
+        amounts[CEIL(0.90 * COUNT(*)) - 1] AS p90, \
+        amounts[CEIL(0.95 * COUNT(*)) - 1] AS p95, \
+        amounts[CEIL(0.99 * COUNT(*)) - 1] AS p99 \
+        FROM ({union}) AS t \
+        GROUP BY t.transaction_type{key} \
+        LETTING amounts = ARRAY_SORT(ARRAY_AGG(t.amount))",
+        projection = group_projection,
+        union = union,
+        key = group_key,
+    );
+
+    fetch_rows(
+        connection_data,
+        "transaction_stats",
+        query,
+        query_options(access, json!({})),
+    )
+    .instrument(query_span)
+    .await
+}
+
+// Keyset pagination ordered by id and document, `after` is the cursor of the last transaction
+// of the previous page
+pub async fn transactions_page(
+    connection_data: &CouchbaseConnection,
+    access: Access,
+    filter: &TransactionFilter,
+    limit: u32,
+    after: Option<&PageCursor>,
+) -> CouchbaseResult<Vec<PagedTransaction>> {
+    let query_span = tracing::info_span!(
+        "Fetching a page of transactions from couchbase",
+        ?access,
+        ?filter,
+        limit,
+        ?after
+    );
+
+    let mut conditions = access_conditions(access);
+    if filter.user_id.is_some() {
+        conditions.push("t.user_id = $user_id");
+    }
+    if filter.min_amount.is_some() {
+        conditions.push("t.amount >= $min_amount");
+    }
+    if filter.max_amount.is_some() {
+        conditions.push("t.amount <= $max_amount");
+    }
+    if after.is_some() {
+        conditions.push(
+            "(t.id > $after_id OR (t.id = $after_id \
+            AND [META(t).keyspace, META(t).id] > [$after_keyspace, $after_key]))",
+        );
+    }
+    let query = union_all(
+        connection_data,
+        filter.transaction_type.as_ref(),
+        &conditions,
+        |keyspace, where_clause| {
+            format!(
+                "SELECT t.*, META(t).keyspace AS document_keyspace, META(t).id AS document_key \
+                FROM {} AS t{}",
+                keyspace, where_clause
+            )
+        },
+    )
+    .await?;
+
+    let options = query_options(
+        access,
+        json!({
+            "user_id": filter.user_id,
+            "min_amount": filter.min_amount,
+            "max_amount": filter.max_amount,
+            "after_id": after.map(|after| after.id),
+            "after_keyspace": after.map(|after| &after.keyspace),
+            "after_key": after.map(|after| &after.key),
+            "limit": limit,
+        }),
+    );
+
+    fetch_rows(
+        connection_data,
+        "transactions_page",
+        format!(
+            "{} ORDER BY id, document_keyspace, document_key LIMIT $limit",
+            query
+        ),
+        options,
+    )
+    .instrument(query_span)
+    .await
+}
+
+pub async fn transaction(
+    connection_data: &CouchbaseConnection,
+    access: Access,
+    id: u64,
+) -> CouchbaseResult<Option<Transaction>> {
+    let query_span = tracing::info_span!("Fetching a transaction from couchbase", ?access, id);
+
+    let mut conditions = vec!["t.id = $id"];
+    conditions.extend(access_conditions(access));
+    let query = union_all(
+        connection_data,
+        None,
+        &conditions,
+        |keyspace, where_clause| format!("SELECT t.* FROM {} AS t{}", keyspace, where_clause),
+    )
+    .await?;
+
+    let options = query_options(access, json!({ "id": id }));
+
+    let transactions: Vec<Transaction> = fetch_rows(connection_data, "transaction", query, options)
+        .instrument(query_span)
+        .await?;
+
+    Ok(transactions.into_iter().next())
+}
+
+// Rows are left in the result so callers can stream them instead of collecting
+pub async fn stream_transactions(
+    connection_data: &CouchbaseConnection,
+    access: Access,
+) -> CouchbaseResult<QueryResult> {
+    let query_span = tracing::info_span!("Streaming transactions from couchbase", ?access);
+
+    let conditions = access_conditions(access);
+    let query = union_all(
+        connection_data,
+        None,
+        &conditions,
+        |keyspace, where_clause| format!("SELECT * FROM {} AS t{}", keyspace, where_clause),
+    )
+    .await?;
+
+    observe_query(
+        "stream_transactions",
+        connection_data
+            .cluster
+            .query(query, query_options(access, json!({}))),
+    )
+    .instrument(query_span)
+    .await
+}
+
+pub async fn ping(connection_data: &CouchbaseConnection) -> CouchbaseResult<()> {
+    let _: Vec<u8> = fetch_rows(
+        connection_data,
+        "ping",
+        "SELECT RAW 1".to_string(),
+        QueryOptions::default(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn bucket_exists(connection_data: &CouchbaseConnection) -> CouchbaseResult<bool> {
+    let options =
+        QueryOptions::default().named_parameters(json!({ "bucket": connection_data.bucket_name }));
+
+    let buckets: Vec<String> = fetch_rows(
+        connection_data,
+        "bucket_exists",
+        "SELECT RAW b.name FROM system:buckets AS b WHERE b.name = $bucket".to_string(),
+        options,
+    )
+    .await?;
+
+    Ok(!buckets.is_empty())
+}
+
+pub async fn scope_exists(connection_data: &CouchbaseConnection) -> CouchbaseResult<bool> {
+    let options = QueryOptions::default().named_parameters(json!({
+        "bucket": connection_data.bucket_name,
+        "scope": connection_data.scope_name,
+    }));
+
+    let scopes: Vec<String> = fetch_rows(
+        connection_data,
+        "scope_exists",
+        "SELECT RAW s.name FROM system:scopes AS s WHERE s.`bucket` = $bucket AND s.name = $scope"
+            .to_string(),
+        options,
+    )
+    .await?;
+
+    Ok(!scopes.is_empty())
+}
+
+pub async fn collection_names(
+    connection_data: &CouchbaseConnection,
+) -> CouchbaseResult<Vec<String>> {
+    let options = QueryOptions::default().named_parameters(json!({
+        "bucket": connection_data.bucket_name,
+        "scope": connection_data.scope_name,
+    }));
+
+    fetch_rows(
+        connection_data,
+        "collection_names",
+        "SELECT RAW k.name FROM system:keyspaces AS k WHERE k.`bucket` = $bucket AND k.`scope` = $scope"
+            .to_string(),
+        options,
+    )
+    .await
+}
+
+pub async fn partitions(
+    connection_data: &CouchbaseConnection,
+    transaction_type: &TransactionType,
+) -> CouchbaseResult<Vec<Partition>> {
+    connection_data
+        .layout
+        .partitions(
+            &connection_data.cluster,
+            &connection_data.bucket_name,
+            &connection_data.scope_name,
+            &[transaction_type.collection_name()],
+        )
+        .await
+}
+
+pub async fn transactions_before(
+    connection_data: &CouchbaseConnection,
+    partition: &Partition,
+    cutoff: u64,
+) -> CouchbaseResult<Vec<StoredTransaction>> {
+    let query_span = tracing::info_span!(
+        "Fetching transactions to archive from couchbase",
+        collection = %partition.collection_name
+    );
+
+    let query = format!(
+        "SELECT META(t).id AS id, t.* FROM {} AS t WHERE t.timestamp < $cutoff{}",
+        keyspace(connection_data, &partition.collection_name),
+        and_predicate(partition)
+    );
+    let options = QueryOptions::default().named_parameters(json!({ "cutoff": cutoff }));
+
+    fetch_rows(connection_data, "transactions_before", query, options)
+        .instrument(query_span)
+        .await
+}
+
+pub async fn count_before(
+    connection_data: &CouchbaseConnection,
+    partition: &Partition,
+    cutoff: u64,
+) -> CouchbaseResult<u64> {
+    let query = format!(
+        "SELECT RAW COUNT(*) FROM {} AS t WHERE t.timestamp < $cutoff{}",
+        keyspace(connection_data, &partition.collection_name),
+        and_predicate(partition)
+    );
+    let options = QueryOptions::default().named_parameters(json!({ "cutoff": cutoff }));
+
+    let counts: Vec<u64> = fetch_rows(connection_data, "count_before", query, options).await?;
+
+    Ok(counts.into_iter().sum())
+}
+
+pub async fn delete_transactions(
+    connection_data: &CouchbaseConnection,
+    partition: &Partition,
+    ids: &[String],
+) -> CouchbaseResult<()> {
+    for chunk in ids.chunks(DELETE_CHUNK_SIZE) {
+        let query = format!(
+            "DELETE FROM {} AS t USE KEYS $ids",
+            keyspace(connection_data, &partition.collection_name)
+        );
+        let options = QueryOptions::default().named_parameters(json!({ "ids": chunk }));
+
+        observe_query(
+            "delete_transactions",
+            connection_data.cluster.query(query, options),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+// Restricts a query to the transactions the caller may read, the user id of the
+// caller is bound by `query_options`
+fn access_conditions(access: Access) -> Vec<&'static str> {
+    match access {
+        Access::All => vec![],
+        Access::User(_) => vec!["t.user_id = $subject_user_id"],
+        Access::Nothing => vec!["FALSE"],
+    }
+}
+
+fn query_options(access: Access, mut parameters: Value) -> QueryOptions {
+    if let Access::User(user_id) = access {
+        parameters["subject_user_id"] = json!(user_id);
+    }
+
+    match parameters.as_object() {
+        Some(object) if object.is_empty() => QueryOptions::default(),
+        _ => QueryOptions::default().named_parameters(parameters),
+    }
+}
+
+fn and_predicate(partition: &Partition) -> String {
+    partition
+        .predicate
+        .as_ref()
+        .map(|predicate| format!(" AND {}", predicate))
+        .unwrap_or_default()
+}
+
+fn keyspace(connection_data: &CouchbaseConnection, collection_name: &str) -> String {
+    format!(
+        "`{}`.`{}`.`{}`",
+        connection_data.bucket_name, connection_data.scope_name, collection_name
+    )
+}
+
+// Generates a SELECT for every partition of the layout holding the requested types
+// (all of them when none is given), `select` receives the keyspace and the WHERE clause.
+// A type may span several partitions, e.g. the months of the monthly layout, so aggregations
+// run over the whole union instead of within its branches.
+async fn union_all(
+    connection_data: &CouchbaseConnection,
+    transaction_type: Option<&TransactionType>,
+    conditions: &[&str],
+    select: impl Fn(&str, &str) -> String,
+) -> CouchbaseResult<String> {
+    let type_names = match transaction_type {
+        Some(transaction_type) => vec![transaction_type.collection_name()],
+        None => TransactionType::ALL
+            .iter()
+            .map(|transaction_type| transaction_type.collection_name())
+            .collect(),
+    };
+
+    let partitions = connection_data
+        .layout
+        .partitions(
+            &connection_data.cluster,
+            &connection_data.bucket_name,
+            &connection_data.scope_name,
+            &type_names,
+        )
+        .await?;
+
+    // No collection was written yet, select from an empty array so the query yields no rows
+    if partitions.is_empty() {
+        return Ok("SELECT * FROM [] AS t".to_string());
+    }
+
+    Ok(partitions
+        .iter()
+        .map(|partition| {
+            let conditions = conditions
+                .iter()
+                .copied()
+                .chain(partition.predicate.as_deref())
+                .collect::<Vec<_>>();
+            let where_clause = if conditions.is_empty() {
+                String::new()
+            } else {
+                format!(" WHERE {}", conditions.join(" AND "))
+            };
+
+            select(
+                &keyspace(connection_data, &partition.collection_name),
+                &where_clause,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" UNION ALL "))
+}
+
+async fn fetch_rows<T>(
+    connection_data: &CouchbaseConnection,
+    kind: &str,
+    query: String,
+    options: QueryOptions,
+) -> CouchbaseResult<Vec<T>>
+where
+    T: DeserializeOwned,
+{
+    let mut data = observe_query(kind, connection_data.cluster.query(query, options)).await?;
+    let mut rows = data.rows::<T>();
+
+    let mut response_rows = vec![];
+    while let Some(row) = rows.next().await {
+        match row {
+            Ok(row) => response_rows.push(row),
+            Err(e) => tracing::error!("Error in row: {}", e),
+        }
+    }
+
+    Ok(response_rows)
+}
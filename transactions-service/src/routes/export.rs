@@ -0,0 +1,138 @@
+use actix_web::{
+    error, get,
+    http::header,
+    web::{self, Bytes},
+    HttpRequest, HttpResponse, Responder,
+};
+use futures::{future, stream, StreamExt};
+
+use crate::{
+    auth::Principal,
+    model::{CouchbaseTransactionWrapper, Transaction},
+    repository, CouchbaseConnection,
+};
+
+#[derive(Debug, Clone, Copy)]
+enum ExportFormat {
+    NdJson,
+    Csv,
+}
+
+impl ExportFormat {
+    fn from_request(request: &HttpRequest) -> Self {
+        let accepts_csv = request
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .map(|accept| accept.contains("text/csv"))
+            .unwrap_or(false);
+
+        if accepts_csv {
+            ExportFormat::Csv
+        } else {
+            ExportFormat::NdJson
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::NdJson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv",
+        }
+    }
+
+    fn header(&self) -> Option<Bytes> {
+        match self {
+            ExportFormat::NdJson => None,
+            ExportFormat::Csv => Some(Bytes::from_static(
+                b"id,user_id,amount,transaction_type,timestamp\n",
+            )),
+        }
+    }
+
+    fn encode(&self, transaction: &Transaction) -> Result<Bytes, actix_web::Error> {
+        match self {
+            ExportFormat::NdJson => {
+                let mut line = serde_json::to_vec(transaction)?;
+                line.push(b'\n');
+                Ok(Bytes::from(line))
+            }
+            ExportFormat::Csv => Ok(Bytes::from(format!(
+                "{},{},{},{:?},{}\n",
+                transaction.id,
+                transaction.user_id,
+                transaction.amount,
+                transaction.transaction_type,
+                transaction
+                    .timestamp
+                    .map(|timestamp| timestamp.to_string())
+                    .unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Exporting transactions for /transactions/export request",
+    skip(request, connection_data, principal)
+)]
+#[utoipa::path(
+    tag = "transactions",
+    context_path = "/v1",
+    responses((
+        status = 200,
+        description = "Every transaction streamed as NDJSON, or as CSV with `Accept: text/csv`",
+        body = Transaction,
+        content_type = ["application/x-ndjson", "text/csv"]
+    ))
+)]
+#[get("/transactions/export")]
+async fn transactions_export(
+    request: HttpRequest,
+    connection_data: web::Data<CouchbaseConnection>,
+    principal: web::ReqData<Principal>,
+) -> impl Responder {
+    let format = ExportFormat::from_request(&request);
+
+    let access = principal.access();
+
+    let mut data = match repository::stream_transactions(&connection_data, access).await {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::error!("Query error: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    // Rows are encoded as they arrive from couchbase, nothing is buffered. A row that fails to
+    // decode ends the body with an error, so clients see an aborted transfer instead of a short export.
+    let rows = data
+        .rows::<CouchbaseTransactionWrapper>()
+        .flat_map(move |row| {
+            let encoded = match row {
+                Ok(wrapper) => wrapper
+                    .inner
+                    .into_values()
+                    .map(|transaction| format.encode(&transaction))
+                    .collect::<Vec<_>>(),
+                Err(e) => {
+                    tracing::error!("Error in row, aborting export: {}", e);
+                    vec![Err(error::ErrorInternalServerError(e))]
+                }
+            };
+            stream::iter(encoded)
+        })
+        .scan(false, |failed, encoded| {
+            let item = (!*failed).then(|| {
+                *failed = encoded.is_err();
+                encoded
+            });
+            future::ready(item)
+        });
+
+    let body = stream::iter(format.header().map(Ok)).chain(rows);
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .streaming(body)
+}
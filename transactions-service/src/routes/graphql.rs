@@ -0,0 +1,36 @@
+use actix_web::{route, web};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+
+use crate::{auth::Principal, graphql::TransactionsSchema};
+
+// Queries see the same transactions as the REST routes of the caller
+#[tracing::instrument(
+    name = "Executing GraphQL query for /graphql request",
+    skip(schema, principal, request)
+)]
+// Also answers GET with the query in the `query` parameter
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    request_body(
+        content = Object,
+        description = "`query` with optional `variables` and `operationName`",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 200, description = "`data` of the query, `errors` of failed fields and of queries rejected for their depth or complexity", body = Object),
+        (status = 400, description = "Not a GraphQL request")
+    )
+)]
+#[route("/graphql", method = "GET", method = "POST")]
+async fn graphql_query(
+    schema: web::Data<TransactionsSchema>,
+    principal: web::ReqData<Principal>,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    schema
+        .execute(request.into_inner().data(principal.access()))
+        .await
+        .into()
+}
@@ -1,6 +1,148 @@
-use actix_web::{get, HttpResponse, Responder};
+use std::{
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use actix_web::{get, web, HttpResponse, Responder};
+use tokio::time::{timeout_at, Instant};
+use transactions_store::layout::CollectionLayout;
+
+use crate::{
+    model::{HealthCheck, HealthReport, HealthStatus, TransactionType},
+    repository, CouchbaseConnection,
+};
+
+#[utoipa::path(tag = "health", responses((status = 200, description = "The server is running")))]
 #[get("/")]
 async fn hello() -> impl Responder {
     HttpResponse::Ok()
 }
+
+// The process is up and serving requests, dependencies are not checked
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, description = "The process is up", body = HealthReport))
+)]
+#[get("/health/live")]
+async fn liveness() -> impl Responder {
+    HttpResponse::Ok().json(HealthReport::from_checks(vec![]))
+}
+
+#[tracing::instrument(name = "Checking readiness", skip(connection_data))]
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "Couchbase and the schema are available", body = HealthReport),
+        (status = 503, description = "A dependency check failed", body = HealthReport)
+    )
+)]
+#[get("/health/ready")]
+async fn readiness(connection_data: web::Data<CouchbaseConnection>) -> impl Responder {
+    let deadline = Instant::now() + READINESS_TIMEOUT;
+
+    let (cluster, bucket, scope, collections) = futures::join!(
+        check("cluster", deadline, async {
+            repository::ping(&connection_data)
+                .await
+                .map_err(|e| e.to_string())
+        }),
+        check("bucket", deadline, async {
+            match repository::bucket_exists(&connection_data).await {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(format!(
+                    "Bucket {} does not exist",
+                    connection_data.bucket_name
+                )),
+                Err(e) => Err(e.to_string()),
+            }
+        }),
+        check("scope", deadline, async {
+            match repository::scope_exists(&connection_data).await {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(format!(
+                    "Scope {} does not exist",
+                    connection_data.scope_name
+                )),
+                Err(e) => Err(e.to_string()),
+            }
+        }),
+        check(
+            "collections",
+            deadline,
+            missing_collections(&connection_data)
+        ),
+    );
+
+    let report = HealthReport::from_checks(vec![cluster, bucket, scope, collections]);
+    if report.status == HealthStatus::Up {
+        HttpResponse::Ok().json(report)
+    } else {
+        tracing::warn!(?report, "Not ready");
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+// The checks run concurrently within this budget, an unreachable cluster makes them fail
+// instead of holding the probe open
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+async fn check(
+    name: &str,
+    deadline: Instant,
+    check: impl Future<Output = Result<(), String>>,
+) -> HealthCheck {
+    let start = Instant::now();
+    let result = timeout_at(deadline, check)
+        .await
+        .unwrap_or_else(|_| Err(format!("Timed out after {:?}", READINESS_TIMEOUT)));
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    match result {
+        Ok(()) => HealthCheck {
+            name: name.to_string(),
+            status: HealthStatus::Up,
+            latency_ms,
+            error: None,
+        },
+        Err(error) => HealthCheck {
+            name: name.to_string(),
+            status: HealthStatus::Down,
+            latency_ms,
+            error: Some(error),
+        },
+    }
+}
+
+// Collections of the per type and single layouts always exist, monthly collections
+// only once a transaction of that month was written
+async fn missing_collections(connection_data: &CouchbaseConnection) -> Result<(), String> {
+    if let CollectionLayout::Monthly = connection_data.layout {
+        return Ok(());
+    }
+
+    let type_names = TransactionType::ALL
+        .iter()
+        .map(|transaction_type| transaction_type.collection_name().to_string())
+        .collect::<Vec<_>>();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_millis() as u64;
+
+    let existing = repository::collection_names(connection_data)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let missing = connection_data
+        .layout
+        .initial_collections(&type_names, now)
+        .into_iter()
+        .filter(|collection_name| !existing.contains(collection_name))
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Missing collections: {}", missing.join(", ")))
+    }
+}
@@ -0,0 +1,20 @@
+use actix_web::{get, http::header::CONTENT_TYPE, HttpResponse, Responder};
+
+use crate::metrics::encode;
+
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, description = "Prometheus text exposition format", content_type = "text/plain"))
+)]
+#[get("/metrics")]
+async fn prometheus_metrics() -> impl Responder {
+    match encode() {
+        Ok((content_type, body)) => HttpResponse::Ok()
+            .insert_header((CONTENT_TYPE, content_type))
+            .body(body),
+        Err(e) => {
+            tracing::error!("Metrics encoding error: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -1,2 +1,9 @@
+pub mod export;
+pub mod graphql;
 pub mod health_check;
+pub mod metrics;
+pub mod openapi;
 pub mod transactions;
+pub mod users;
+pub mod v1;
+pub mod v2;
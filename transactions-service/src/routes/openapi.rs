@@ -0,0 +1,13 @@
+use actix_web::{get, HttpResponse, Responder};
+use utoipa::OpenApi;
+
+use crate::openapi::ApiDoc;
+
+#[utoipa::path(
+    tag = "documentation",
+    responses((status = 200, description = "This OpenAPI 3 document", body = Object))
+)]
+#[get("/openapi.json")]
+async fn openapi_spec() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
@@ -1,111 +1,99 @@
 use actix_web::{get, web, HttpResponse, Responder};
-use couchbase::QueryOptions;
-use futures::StreamExt;
-use tracing::Instrument;
+use serde::Deserialize;
+use utoipa::IntoParams;
 
 use crate::{
-    model::{CouchbaseTransactionWrapper, Transaction},
-    CouchbaseConnection,
+    auth::Principal,
+    model::{StatsGrouping, Transaction, TransactionStats, TransactionType},
+    repository, CouchbaseConnection,
 };
 
+#[derive(Debug, Deserialize, IntoParams)]
+struct StatsQuery {
+    /// Groups the statistics per type additionally by user or time bucket
+    group_by: Option<StatsGrouping>,
+}
+
+#[tracing::instrument(
+    name = "Getting transaction statistics for /transactions/stats request",
+    skip(connection_data, principal)
+)]
+#[utoipa::path(
+    tag = "transactions",
+    context_path = "/v1",
+    responses((status = 200, description = "Amount statistics per transaction type", body = [TransactionStats]))
+)]
+#[get("/transactions/stats")]
+async fn transactions_stats(
+    connection_data: web::Data<CouchbaseConnection>,
+    principal: web::ReqData<Principal>,
+    query: web::Query<StatsQuery>,
+) -> impl Responder {
+    let access = principal.access();
+
+    match repository::transaction_stats(&connection_data, access, query.group_by).await {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(e) => {
+            tracing::error!("Query error: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
 #[tracing::instrument(
     name = "Getting transactions by type for /transactions/ request",
-    skip(connection_data)
+    skip(connection_data, principal)
+)]
+#[utoipa::path(
+    tag = "transactions",
+    context_path = "/v1",
+    params(("type" = String, Path, description = "Transaction type, e.g. deposit")),
+    responses(
+        (status = 200, description = "Transactions of the type", body = [Transaction]),
+        (status = 404, description = "Unknown transaction type")
+    )
 )]
 #[get("/transactions/{type}")]
 async fn transactions_by_type(
     connection_data: web::Data<CouchbaseConnection>,
+    principal: web::ReqData<Principal>,
     path: web::Path<String>,
 ) -> impl Responder {
-    let transaction_type = path.into_inner();
-    let query_span =
-        tracing::info_span!("Fetching {} transactions from couchbase", transaction_type);
-
-    let query = format!(
-        "SELECT * FROM `{}`.`{}`.`{}`",
-        connection_data.bucket_name, connection_data.scope_name, transaction_type
-    );
+    let transaction_type = match path.into_inner().parse::<TransactionType>() {
+        Ok(transaction_type) => transaction_type,
+        Err(e) => return HttpResponse::NotFound().body(e),
+    };
 
-    let result = connection_data
-        .cluster
-        .query(query, QueryOptions::default())
-        .instrument(query_span)
-        .await;
-
-    let mut response_rows: Vec<Transaction> = vec![];
-
-    match result {
-        Ok(mut data) => {
-            let mut rows = data.rows::<CouchbaseTransactionWrapper>();
-
-            while let Some(row) = rows.next().await {
-                match row {
-                    Ok(wrapper) => {
-                        for (_, transaction) in wrapper.inner {
-                            response_rows.push(transaction);
-                        }
-                    }
-                    Err(e) => tracing::error!("Error in row: {}", e),
-                }
-            }
+    match repository::transactions(&connection_data, principal.access(), Some(transaction_type))
+        .await
+    {
+        Ok(transactions) => HttpResponse::Ok().json(transactions),
+        Err(e) => {
+            tracing::error!("Query error: {}", e);
+            HttpResponse::InternalServerError().finish()
         }
-        Err(e) => tracing::error!("Query error: {}", e),
     }
-
-    HttpResponse::Ok().json(response_rows)
 }
 
 #[tracing::instrument(
     name = "Getting transactions for /transactions/ request",
-    skip(connection_data)
+    skip(connection_data, principal)
+)]
+#[utoipa::path(
+    tag = "transactions",
+    context_path = "/v1",
+    responses((status = 200, description = "Transactions of every type", body = [Transaction]))
 )]
 #[get("/transactions")]
-async fn transactions(connection_data: web::Data<CouchbaseConnection>) -> impl Responder {
-    let query_span = tracing::info_span!("Fetching transactions from couchbase");
-
-    let query = format!(
-        "SELECT * FROM `{}`.`{}`.`bet` \
-        UNION ALL \
-        SELECT * FROM `{}`.`{}`.`trade`\
-        UNION ALL \
-        SELECT * FROM `{}`.`{}`.`deposit` \
-        UNION ALL \
-        SELECT * FROM `{}`.`{}`.`withdrawal`",
-        connection_data.bucket_name,
-        connection_data.scope_name,
-        connection_data.bucket_name,
-        connection_data.scope_name,
-        connection_data.bucket_name,
-        connection_data.scope_name,
-        connection_data.bucket_name,
-        connection_data.scope_name
-    );
-
-    let result = connection_data
-        .cluster
-        .query(query, QueryOptions::default())
-        .instrument(query_span)
-        .await;
-
-    let mut response_rows: Vec<Transaction> = vec![];
-
-    match result {
-        Ok(mut data) => {
-            let mut rows = data.rows::<CouchbaseTransactionWrapper>();
-
-            while let Some(row) = rows.next().await {
-                match row {
-                    Ok(wrapper) => {
-                        for (_, transaction) in wrapper.inner {
-                            response_rows.push(transaction);
-                        }
-                    }
-                    Err(e) => tracing::error!("Error in row: {}", e),
-                }
-            }
+async fn transactions(
+    connection_data: web::Data<CouchbaseConnection>,
+    principal: web::ReqData<Principal>,
+) -> impl Responder {
+    match repository::transactions(&connection_data, principal.access(), None).await {
+        Ok(transactions) => HttpResponse::Ok().json(transactions),
+        Err(e) => {
+            tracing::error!("Query error: {}", e);
+            HttpResponse::InternalServerError().finish()
         }
-        Err(e) => tracing::error!("Query error: {}", e),
     }
-
-    HttpResponse::Ok().json(response_rows)
 }
@@ -0,0 +1,61 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::{
+    auth::Principal,
+    model::{Transaction, UserBalance},
+    repository, CouchbaseConnection,
+};
+
+#[tracing::instrument(
+    name = "Getting user transactions for /users/{user_id}/transactions request",
+    skip(connection_data, principal)
+)]
+#[utoipa::path(
+    tag = "users",
+    context_path = "/v1",
+    params(("user_id" = u64, Path, description = "User id")),
+    responses((status = 200, description = "Transactions of the user", body = [Transaction]))
+)]
+#[get("/users/{user_id}/transactions")]
+async fn user_transactions(
+    connection_data: web::Data<CouchbaseConnection>,
+    principal: web::ReqData<Principal>,
+    path: web::Path<u64>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+
+    match repository::user_transactions(&connection_data, principal.access(), user_id).await {
+        Ok(transactions) => HttpResponse::Ok().json(transactions),
+        Err(e) => {
+            tracing::error!("Query error: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Getting user balance for /users/{user_id}/balance request",
+    skip(connection_data, principal)
+)]
+#[utoipa::path(
+    tag = "users",
+    context_path = "/v1",
+    params(("user_id" = u64, Path, description = "User id")),
+    responses((status = 200, description = "Deposits, refunds and bonuses minus every other type", body = UserBalance))
+)]
+#[get("/users/{user_id}/balance")]
+async fn user_balance(
+    connection_data: web::Data<CouchbaseConnection>,
+    principal: web::ReqData<Principal>,
+    path: web::Path<u64>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+
+    match repository::user_totals(&connection_data, principal.access(), user_id).await {
+        Ok(totals) => HttpResponse::Ok().json(UserBalance::from_totals(user_id, totals)),
+        Err(e) => {
+            tracing::error!("Query error: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use actix_web::web;
+
+use super::{
+    export::transactions_export,
+    transactions::{transactions, transactions_by_type, transactions_stats},
+    users::{user_balance, user_transactions},
+};
+
+// Mounted on /v1 and, deprecated, on the root
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .service(transactions)
+        // Registered before `/transactions/{type}` which would otherwise match them
+        .service(transactions_stats)
+        .service(transactions_export)
+        .service(transactions_by_type)
+        .service(user_transactions)
+        .service(user_balance);
+}
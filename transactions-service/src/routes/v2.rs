@@ -0,0 +1,101 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::{
+    auth::Principal,
+    model::{
+        Page, PageCursor, Pagination, TransactionFilter, TransactionPage, TransactionType,
+        DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE,
+    },
+    repository, CouchbaseConnection,
+};
+
+// Responses are wrapped in a `Page` envelope carrying the pagination metadata
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.service(transactions);
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+struct PageQuery {
+    /// Only transactions of this type, e.g. deposit
+    #[serde(rename = "type")]
+    transaction_type: Option<String>,
+    /// Page size, at most 1000
+    limit: Option<u32>,
+    /// `next` cursor of the previous page
+    after: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Getting a page of transactions for /v2/transactions request",
+    skip(connection_data, principal)
+)]
+#[utoipa::path(
+    tag = "transactions",
+    context_path = "/v2",
+    responses(
+        (status = 200, description = "A page of transactions ordered by id", body = TransactionPage),
+        (status = 400, description = "Invalid type, limit or cursor")
+    )
+)]
+#[get("/transactions")]
+async fn transactions(
+    connection_data: web::Data<CouchbaseConnection>,
+    principal: web::ReqData<Principal>,
+    query: web::Query<PageQuery>,
+) -> impl Responder {
+    let transaction_type = match query
+        .transaction_type
+        .as_deref()
+        .map(str::parse::<TransactionType>)
+        .transpose()
+    {
+        Ok(transaction_type) => transaction_type,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return HttpResponse::BadRequest().body(format!("limit has to be 1 to {}", MAX_PAGE_SIZE));
+    }
+    let after = match query
+        .after
+        .as_deref()
+        .map(str::parse::<PageCursor>)
+        .transpose()
+    {
+        Ok(after) => after,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let filter = TransactionFilter {
+        transaction_type,
+        ..TransactionFilter::default()
+    };
+
+    match repository::transactions_page(
+        &connection_data,
+        principal.access(),
+        &filter,
+        limit,
+        after.as_ref(),
+    )
+    .await
+    {
+        Ok(page) => {
+            let next = match page.last() {
+                Some(last) if page.len() == limit as usize => Some(last.cursor().encode()),
+                _ => None,
+            };
+
+            HttpResponse::Ok().json(Page {
+                data: page.into_iter().map(|paged| paged.transaction).collect(),
+                pagination: Pagination { limit, next },
+            })
+        }
+        Err(e) => {
+            tracing::error!("Query error: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey, ServerConfig,
+};
+use rustls_pemfile::Item;
+
+use crate::configuration::TlsSettings;
+
+// Serves the certificate last loaded from disk, replaced in place when the files change
+pub struct CertificateResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    certified_key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateResolver {
+    pub fn new(settings: &TlsSettings) -> Result<Arc<Self>, String> {
+        let certified_key = load_certified_key(&settings.cert, &settings.key)?;
+
+        Ok(Arc::new(CertificateResolver {
+            cert_path: settings.cert.clone(),
+            key_path: settings.key.clone(),
+            certified_key: RwLock::new(Arc::new(certified_key)),
+        }))
+    }
+
+    fn reload(&self) -> Result<(), String> {
+        let certified_key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self
+            .certified_key
+            .write()
+            .expect("Certificate lock is poisoned") = Arc::new(certified_key);
+
+        Ok(())
+    }
+
+    // Polls the modification times of the certificate and key, e.g. for cert-manager renewals.
+    // A failed reload keeps serving the previous certificate.
+    pub fn watch(self: &Arc<Self>, interval: Duration) {
+        let resolver = self.clone();
+        thread::spawn(move || {
+            let mut modified = resolver.modified();
+            loop {
+                thread::sleep(interval);
+
+                let current = resolver.modified();
+                if current == modified {
+                    continue;
+                }
+                modified = current;
+
+                match resolver.reload() {
+                    Ok(()) => tracing::info!(
+                        cert = %resolver.cert_path.display(),
+                        "Reloaded TLS certificate"
+                    ),
+                    Err(e) => tracing::error!("Failed to reload TLS certificate: {}", e),
+                }
+            }
+        });
+    }
+
+    fn modified(&self) -> [Option<SystemTime>; 2] {
+        [&self.cert_path, &self.key_path].map(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.certified_key
+                .read()
+                .expect("Certificate lock is poisoned")
+                .clone(),
+        )
+    }
+}
+
+pub fn server_config(settings: &TlsSettings) -> Result<ServerConfig, String> {
+    let resolver = CertificateResolver::new(settings)?;
+    resolver.watch(Duration::from_secs(settings.reload_interval_secs));
+
+    Ok(ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver))
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, String> {
+    let certs = read_pem(cert_path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(format!("No certificate in {}", cert_path.display()));
+    }
+
+    let key = read_pem(key_path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(der) | Item::PKCS8Key(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No private key in {}", key_path.display()))?;
+    let key = sign::any_supported_type(&key)
+        .map_err(|e| format!("Unsupported private key in {}: {}", key_path.display(), e))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+fn read_pem(path: &Path) -> Result<Vec<Item>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| format!("Failed to read PEM from {}: {}", path.display(), e))
+}
@@ -0,0 +1,55 @@
+use std::future::Future;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderName, HeaderValue},
+    Error,
+};
+
+// Routes of every version share the auth policy of their unversioned path, and its rate limit
+// unless they are limited on their own
+pub const VERSIONS: &[&str] = &["/v1", "/v2"];
+
+// Unversioned aliases of the v1 routes answer until this date (RFC 8594)
+pub const SUNSET: &str = "Wed, 30 Jun 2027 23:59:59 GMT";
+
+pub fn unversioned(route: &str) -> &str {
+    VERSIONS
+        .iter()
+        .find_map(|version| route.strip_prefix(version))
+        .filter(|rest| rest.starts_with('/'))
+        .unwrap_or(route)
+}
+
+// Used with `Scope::wrap_fn` on the unversioned aliases, points clients to the /v1 route
+pub fn deprecated<S, B>(
+    request: ServiceRequest,
+    service: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let successor = format!("</v1{}>; rel=\"successor-version\"", request.path());
+    let response = service.call(request);
+
+    async move {
+        let mut response = response.await?;
+
+        let headers = response.headers_mut();
+        headers.insert(
+            HeaderName::from_static("deprecation"),
+            HeaderValue::from_static("true"),
+        );
+        headers.insert(
+            HeaderName::from_static("sunset"),
+            HeaderValue::from_static(SUNSET),
+        );
+        if let Ok(link) = HeaderValue::from_str(&successor) {
+            headers.insert(header::LINK, link);
+        }
+
+        Ok(response)
+    }
+}
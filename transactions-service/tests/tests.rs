@@ -1,28 +1,82 @@
-use std::{net::TcpListener, time::Duration};
+use std::{
+    net::TcpListener,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use actix_web::{web, App, HttpResponse, HttpServer};
 use couchbase::{
-    Collection, CollectionSpec, CreateCollectionOptions, CreatePrimaryQueryIndexOptions,
-    CreateScopeOptions, DropScopeOptions, GetAllQueryIndexOptions, Scope, UpsertOptions,
+    Cluster, Collection, CouchbaseError, CreateScopeOptions, DropScopeOptions, GetOptions,
+    QueryOptions, QueryScanConsistency, RemoveOptions, UpsertOptions,
 };
+use futures::StreamExt;
+use jsonwebtoken::{encode, EncodingKey, Header};
 use once_cell::sync::Lazy;
+use opentelemetry::{global, trace::TracerProvider as _};
+use opentelemetry_sdk::{testing::trace::InMemorySpanExporter, trace::TracerProvider};
 use rand::Rng;
+use serde::Serialize;
 use tokio::time::sleep;
 use transactions_service::{
-    configuration::get_configuration,
-    model::Transaction,
+    auth::{Authenticator, API_KEY_HEADER},
+    configuration::{get_configuration_for, Environment, TlsSettings},
+    graphql::build_schema,
+    model::{
+        HealthReport, HealthStatus, Page, Transaction, TransactionStats, TransactionType,
+        UserBalance,
+    },
+    openapi::ApiDoc,
+    rate_limit::{RateLimiter, REMAINING_HEADER},
     telemetry::{get_subscriber, init_subscriber},
+    tls::server_config,
+    versioning::SUNSET,
     CouchbaseConnection,
 };
+use transactions_store::{
+    layout::CollectionLayout,
+    migrations::{migration_document, run_migrations, MIGRATIONS},
+    provisioning::{provision, provision_collection, Schema, SECONDARY_INDEXES},
+};
+use utoipa::OpenApi;
+use uuid::Uuid;
+
+// Keys and secret of configuration/test.yml
+const API_KEY: &str = "test-key";
+const READ_ONLY_API_KEY: &str = "test-read-only-key";
+const JWT_SECRET: &str = "test-hmac-secret";
+
+#[derive(Serialize)]
+struct Claims {
+    sub: String,
+    scope: String,
+    exp: u64,
+}
+
+// Spans of every test end up here instead of an OTLP collector
+static SPAN_EXPORTER: Lazy<InMemorySpanExporter> = Lazy::new(InMemorySpanExporter::default);
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
 
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(SPAN_EXPORTER.clone())
+        .build();
+    let tracer = provider.tracer("test");
+    global::set_tracer_provider(provider);
+
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            tracer,
+        );
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber =
+            get_subscriber(subscriber_name, default_filter_level, std::io::sink, tracer);
         init_subscriber(subscriber);
     };
 });
@@ -30,12 +84,106 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 pub struct TestApp {
     pub address: String,
     pub connection_data: CouchbaseConnection,
+    // Declared last so the scope outlives everything else of the app
+    pub scope: TestScope,
+}
+
+// Drops the scope of a test when it goes out of scope, also when the test panics
+pub struct TestScope {
+    cluster: Arc<Cluster>,
+    bucket_name: String,
+    scope_name: String,
+}
+
+impl Drop for TestScope {
+    fn drop(&mut self) {
+        let cluster = self.cluster.clone();
+        let bucket_name = self.bucket_name.clone();
+        let scope_name = self.scope_name.clone();
+
+        // Drop cannot await and the runtime of the test may already be shutting down,
+        // so the scope is dropped on a runtime of its own
+        let teardown = std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build teardown runtime")
+                .block_on(async {
+                    remove_migration_records(&cluster, &bucket_name, &scope_name).await;
+                    drop_scope(&cluster, &bucket_name, &scope_name).await;
+                })
+        });
+        let _ = teardown.join();
+    }
+}
+
+impl TestScope {
+    // Provisions the collection like the services do and upserts the documents keyed by their
+    // `id`. Returns once every index of the collection covers them, so queries see them at once.
+    async fn seed(&self, collection_name: &str, documents: &[serde_json::Value]) -> Collection {
+        provision_collection(
+            &self.cluster,
+            &self.bucket_name,
+            &self.scope_name,
+            collection_name,
+        )
+        .await
+        .expect("Collection provisioning failed");
+
+        let collection = self
+            .cluster
+            .bucket(&self.bucket_name)
+            .scope(&self.scope_name)
+            .collection(collection_name);
+        for document in documents {
+            let key = match &document["id"] {
+                serde_json::Value::String(id) => id.clone(),
+                id => id.to_string(),
+            };
+            collection
+                .upsert(key, document.clone(), UpsertOptions::default())
+                .await
+                .expect("Error upserting document");
+        }
+
+        // A request_plus scan waits until its index caught up with every earlier mutation
+        let keyspace = format!(
+            "`{}`.`{}`.`{}`",
+            self.bucket_name, self.scope_name, collection_name
+        );
+        let mut scans = vec![format!(
+            "SELECT RAW META(t).id FROM {} AS t USE INDEX (`#primary`)",
+            keyspace
+        )];
+        scans.extend(SECONDARY_INDEXES.iter().map(|(index_name, fields)| {
+            format!(
+                "SELECT RAW META(t).id FROM {} AS t USE INDEX (`{}`) WHERE t.`{}` IS NOT MISSING",
+                keyspace, index_name, fields[0]
+            )
+        }));
+        for scan in scans {
+            let mut result = self
+                .cluster
+                .query(
+                    scan,
+                    QueryOptions::default().scan_consistency(QueryScanConsistency::RequestPlus),
+                )
+                .await
+                .expect("Error scanning the seeded collection");
+            let mut rows = result.rows::<String>();
+            while let Some(row) = rows.next().await {
+                row.expect("Error scanning the seeded collection");
+            }
+        }
+
+        collection
+    }
 }
 
 #[actix_web::test]
 async fn servers_is_working() {
     // Given
-    let app_data = spawn_app("test".to_string()).await;
+    let app_data = spawn_app().await;
     let client = reqwest::Client::new();
 
     // When
@@ -50,199 +198,1508 @@ async fn servers_is_working() {
 }
 
 #[actix_web::test]
-async fn get_transactions_returns_empty_json_when_no_rows() {
+async fn liveness_returns_ok() {
     // Given
+    let app_data = spawn_app().await;
     let client = reqwest::Client::new();
-    let app_data = spawn_app("test".to_string()).await;
-    let mut con = app_data.connection_data;
-    create_scope(&con).await;
-    con.collection_name = "withdrawal".to_string();
-    create_collection(&con).await;
-    manage_db_indexing(&con).await;
 
     // When
     let response = client
-        .get(&format!(
-            "{}/transactions/{}",
-            &app_data.address, con.collection_name
-        ))
+        .get(&format!("{}/health/live", &app_data.address))
         .send()
         .await
         .expect("Failed to execute request.");
 
     // Then
     assert_eq!(200, response.status().as_u16());
-
-    drop_scope(&con).await
 }
 
 #[actix_web::test]
-async fn get_transactions_by_type_returns_a_transactions_from_db() {
+async fn readiness_returns_ok_once_the_schema_is_provisioned() {
     // Given
-    let mut rng = rand::thread_rng();
-    let test_id: u32 = rng.gen();
-    let scope_name = format!("{}test", test_id);
-
-    let app_data = spawn_app(scope_name.clone()).await;
-    let mut con = app_data.connection_data;
-
+    let app_data = spawn_app().await;
     let client = reqwest::Client::new();
+    let con = &app_data.connection_data;
+    let schema = Schema {
+        bucket_name: con.bucket_name.clone(),
+        scope_name: con.scope_name.clone(),
+        collections: TransactionType::ALL
+            .iter()
+            .map(|transaction_type| transaction_type.collection_name().to_string())
+            .collect(),
+    };
+    provision(&con.cluster, &schema)
+        .await
+        .expect("Schema provisioning failed");
 
-    create_scope(&con).await;
-    con.collection_name = "withdrawal".to_string();
-    let collection = create_collection(&con).await;
-    sleep(Duration::from_secs(5)).await;
-
-    manage_db_indexing(&con).await;
+    // When
+    let response = client
+        .get(&format!("{}/health/ready", &app_data.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
 
-    let transaction: Transaction = serde_json::from_str(r#"{"id":1447241290163152320,"user_id":18107235828171665340,"amount":678.7329504848955,"transaction_type":"Withdrawal"}"#).expect("Error deserializing the message");
+    // Then
+    assert_eq!(200, response.status().as_u16());
 
-    collection
-        .upsert(
-            transaction.id.to_string(),
-            transaction.clone(),
-            UpsertOptions::default(),
-        )
+    let report: HealthReport = response
+        .json()
         .await
-        .expect("Error upserting transaction");
+        .expect("Failed to deserialize response");
 
-    sleep(Duration::from_secs(5)).await;
+    assert_eq!(HealthStatus::Up, report.status);
+    assert_eq!(4, report.checks.len());
+    assert!(report
+        .checks
+        .iter()
+        .all(|check| check.status == HealthStatus::Up));
+}
+
+#[actix_web::test]
+async fn readiness_fails_when_collections_are_missing() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
 
     // When
     let response = client
-        .get(&format!(
-            "{}/transactions/{}",
-            &app_data.address, con.collection_name
-        ))
+        .get(&format!("{}/health/ready", &app_data.address))
         .send()
         .await
         .expect("Failed to execute request.");
 
     // Then
-    assert_eq!(200, response.status().as_u16());
+    assert_eq!(503, response.status().as_u16());
 
-    let response_body: Vec<Transaction> = response
+    let report: HealthReport = response
         .json()
         .await
         .expect("Failed to deserialize response");
 
-    assert_eq!(1, response_body.len());
+    assert_eq!(HealthStatus::Down, report.status);
+    let scope = report
+        .checks
+        .iter()
+        .find(|check| check.name == "scope")
+        .expect("Scope check missing");
+    assert_eq!(HealthStatus::Up, scope.status);
+    let collections = report
+        .checks
+        .iter()
+        .find(|check| check.name == "collections")
+        .expect("Collections check missing");
+    assert_eq!(HealthStatus::Down, collections.status);
+    let cluster = report
+        .checks
+        .iter()
+        .find(|check| check.name == "cluster")
+        .expect("Cluster check missing");
+    assert_eq!(HealthStatus::Up, cluster.status);
+}
+
+#[actix_web::test]
+async fn requests_without_credentials_are_unauthorized() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    // When
+    let response = client
+        .get(&format!("{}/v1/transactions", &app_data.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
 
-    drop_scope(&con).await;
+    // Then
+    assert_eq!(401, response.status().as_u16());
 }
 
-async fn spawn_app(scope_name: String) -> TestApp {
-    Lazy::force(&TRACING);
+#[actix_web::test]
+async fn export_requires_export_scope() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
 
-    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind to random port");
-    let port = listener.local_addr().unwrap().port();
-    let address = format!("http://127.0.0.1:{}", port);
+    // When
+    let response = client
+        .get(&format!("{}/v1/transactions/export", &app_data.address))
+        .header(API_KEY_HEADER, READ_ONLY_API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Then
+    assert_eq!(403, response.status().as_u16());
+}
 
-    let configuration = get_configuration().expect("Failed to read configuration.");
+#[actix_web::test]
+async fn export_streams_transactions_as_ndjson_or_csv() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
 
-    let mut connection_data = CouchbaseConnection::test_connection(&configuration);
-    connection_data.scope_name = scope_name;
+    let transactions = [1, 2].map(|id| {
+        serde_json::json!({
+            "id": id,
+            "user_id": 42,
+            "amount": 100.0,
+            "transaction_type": "Deposit",
+            "timestamp": 1700000000000u64
+        })
+    });
+    app_data.scope.seed("deposit", &transactions).await;
 
-    let server = transactions_service::run(listener, connection_data.clone())
+    // When
+    let ndjson = client
+        .get(&format!("{}/v1/transactions/export", &app_data.address))
+        .header(API_KEY_HEADER, API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let csv = client
+        .get(&format!("{}/v1/transactions/export", &app_data.address))
+        .header(API_KEY_HEADER, API_KEY)
+        .header("Accept", "text/csv")
+        .send()
         .await
-        .expect("Server initialization failed.");
+        .expect("Failed to execute request.");
 
-    tokio::spawn(server);
+    // Then
+    assert_eq!(200, ndjson.status().as_u16());
+    assert_eq!("application/x-ndjson", ndjson.headers()["Content-Type"]);
+    let mut ids = ndjson
+        .text()
+        .await
+        .expect("Failed to read response")
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<Transaction>(line)
+                .expect("Failed to deserialize line")
+                .id
+        })
+        .collect::<Vec<_>>();
+    ids.sort();
+    assert_eq!(vec![1, 2], ids);
 
-    TestApp {
-        address,
-        connection_data,
-    }
+    assert_eq!(200, csv.status().as_u16());
+    assert_eq!("text/csv", csv.headers()["Content-Type"]);
+    let csv = csv.text().await.expect("Failed to read response");
+    let mut lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(
+        "id,user_id,amount,transaction_type,timestamp",
+        lines.remove(0)
+    );
+    lines.sort();
+    assert_eq!(
+        vec![
+            "1,42,100,Deposit,1700000000000",
+            "2,42,100,Deposit,1700000000000"
+        ],
+        lines
+    );
 }
 
-async fn create_collection(con: &CouchbaseConnection) -> Collection {
-    let bucket = con.cluster.bucket(&con.bucket_name);
-    let mgr = bucket.collections();
+#[actix_web::test]
+async fn export_is_aborted_by_rows_that_fail_to_decode() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
 
-    match mgr
-        .create_collection(
-            CollectionSpec::new(
-                &con.collection_name,
-                &con.scope_name,
-                Duration::from_secs(0),
-            ),
-            CreateCollectionOptions::default(),
-        )
+    app_data
+        .scope
+        .seed("deposit", &[serde_json::json!({ "id": "not a number" })])
+        .await;
+
+    // When
+    let response = client
+        .get(&format!("{}/v1/transactions/export", &app_data.address))
+        .header(API_KEY_HEADER, API_KEY)
+        .send()
+        .await;
+
+    // Then
+    // The connection is closed mid-response, depending on timing before or after the headers
+    let body = match response {
+        Ok(response) => response.text().await,
+        Err(e) => Err(e),
+    };
+    assert!(body.is_err(), "Export completed despite a malformed row");
+}
+
+#[actix_web::test]
+async fn bearer_token_scopes_are_enforced() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let token = jwt("test-user", "transactions:read", JWT_SECRET);
+
+    // When
+    let response = client
+        .get(&format!("{}/v1/transactions/export", &app_data.address))
+        .bearer_auth(token)
+        .send()
         .await
-    {
-        Ok(_result) => {
-            tracing::debug!("Collection created");
-        }
-        Err(e) => tracing::debug!("Create collection error: {}", e),
-    }
+        .expect("Failed to execute request.");
 
-    bucket
-        .scope(&con.scope_name)
-        .collection(&con.collection_name)
+    // Then
+    assert_eq!(403, response.status().as_u16());
 }
 
-async fn create_scope(con: &CouchbaseConnection) -> Scope {
-    let bucket = con.cluster.bucket(&con.bucket_name);
-    let mgr = bucket.collections();
+#[actix_web::test]
+async fn bearer_token_with_invalid_signature_is_unauthorized() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
 
-    match mgr
-        .create_scope(&con.scope_name, CreateScopeOptions::default())
+    let token = jwt(
+        "test-user",
+        "transactions:read transactions:export",
+        "another-secret",
+    );
+
+    // When
+    let response = client
+        .get(&format!("{}/v1/transactions/export", &app_data.address))
+        .bearer_auth(token)
+        .send()
         .await
-    {
-        Ok(_result) => {
-            tracing::debug!("Scope created");
+        .expect("Failed to execute request.");
+
+    // Then
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_web::test]
+async fn guessing_credentials_is_rate_limited() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    // When
+    let mut limited = None;
+    for attempt in 0..30 {
+        let response = client
+            .get(&format!("{}/v1/transactions", &app_data.address))
+            .header(API_KEY_HEADER, format!("guess-{}", attempt))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        if response.status().as_u16() == 429 {
+            limited = Some(response);
+            break;
         }
-        Err(e) => tracing::debug!("Create scope error: {}", e),
+        assert_eq!(401, response.status().as_u16());
     }
 
-    bucket.scope(&con.scope_name)
+    // Then
+    let limited = limited.expect("Guesses were never rate limited");
+    assert!(limited.headers().contains_key("Retry-After"));
+    assert_eq!("0", limited.headers()[REMAINING_HEADER]);
 }
 
-async fn drop_scope(con: &CouchbaseConnection) {
-    let bucket = con.cluster.bucket(&con.bucket_name);
-    let mgr = bucket.collections();
+#[actix_web::test]
+async fn rate_limited_clients_get_too_many_requests() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
 
-    match mgr
-        .drop_scope(&con.scope_name, DropScopeOptions::default())
-        .await
-    {
-        Ok(_) => {
-            tracing::debug!("{} scope deleted", &con.collection_name);
-        }
-        Err(e) => {
-            tracing::error!("Error deleting scope: {:?}", e)
+    // When
+    let mut limited = None;
+    for _ in 0..20 {
+        let response = client
+            .get(&format!("{}/v1/transactions", &app_data.address))
+            .header(API_KEY_HEADER, API_KEY)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        if response.status().as_u16() == 429 {
+            limited = Some(response);
+            break;
         }
+        assert_eq!(200, response.status().as_u16());
     }
+    let other_client = client
+        .get(&format!("{}/v1/transactions", &app_data.address))
+        .header(API_KEY_HEADER, READ_ONLY_API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Then
+    let limited = limited.expect("Requests were never rate limited");
+    let retry_after: u64 = limited
+        .headers()
+        .get("Retry-After")
+        .expect("Missing Retry-After header")
+        .to_str()
+        .unwrap()
+        .parse()
+        .expect("Retry-After is not a number of seconds");
+    assert!(retry_after > 0);
+    assert_eq!("0", limited.headers()[REMAINING_HEADER]);
+
+    // Buckets are per client
+    assert_eq!(200, other_client.status().as_u16());
 }
 
-async fn manage_db_indexing(connection: &CouchbaseConnection) {
-    let index_manager = connection.cluster.query_indexes();
+#[actix_web::test]
+async fn unversioned_routes_are_deprecated_aliases_of_v1() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    // When
+    let versioned = client
+        .get(&format!("{}/v1/transactions", &app_data.address))
+        .header(API_KEY_HEADER, API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let alias = client
+        .get(&format!("{}/transactions", &app_data.address))
+        .header(API_KEY_HEADER, API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Then
+    assert_eq!(200, versioned.status().as_u16());
+    assert!(versioned.headers().get("Deprecation").is_none());
 
-    let name = format!(
-        "{}`.`{}`.`{}",
-        &connection.bucket_name, &connection.scope_name, &connection.collection_name
+    assert_eq!(200, alias.status().as_u16());
+    assert_eq!("true", alias.headers()["Deprecation"]);
+    assert_eq!(SUNSET, alias.headers()["Sunset"]);
+    assert_eq!(
+        r#"</v1/transactions>; rel="successor-version""#,
+        alias.headers()["Link"]
     );
+}
 
-    match index_manager
-        .get_all_indexes(&name, GetAllQueryIndexOptions::default())
+#[actix_web::test]
+async fn v2_transactions_are_paginated_in_an_envelope() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let transactions = [1, 2, 3].map(|id| {
+        serde_json::json!({
+            "id": id,
+            "user_id": 42,
+            "amount": 100.0,
+            "transaction_type": "Deposit"
+        })
+    });
+    app_data.scope.seed("deposit", &transactions).await;
+
+    // When
+    let first: Page<Transaction> = client
+        .get(&format!("{}/v2/transactions?limit=2", &app_data.address))
+        .header(API_KEY_HEADER, API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .expect("Failed to deserialize response");
+    let next = first.pagination.next.clone().expect("Missing next cursor");
+    let second: Page<Transaction> = client
+        .get(&format!(
+            "{}/v2/transactions?limit=2&after={}",
+            &app_data.address, next
+        ))
+        .header(API_KEY_HEADER, API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
         .await
+        .expect("Failed to deserialize response");
+
+    // Then
+    assert_eq!(
+        vec![1, 2],
+        first.data.iter().map(|t| t.id).collect::<Vec<_>>()
+    );
+    assert_eq!(2, first.pagination.limit);
+    assert_eq!(
+        vec![3],
+        second.data.iter().map(|t| t.id).collect::<Vec<_>>()
+    );
+    assert!(second.pagination.next.is_none());
+}
+
+// Ids may repeat across collections, pages continue behind the document of the cursor
+#[actix_web::test]
+async fn v2_pages_keep_transactions_with_repeated_ids() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    for (collection_name, transaction_type, ids) in
+        [("deposit", "Deposit", vec![1, 2]), ("bet", "Bet", vec![1])]
     {
-        Ok(results) => {
-            if !results.into_iter().any(|index| index.is_primary()) {
-                match index_manager
-                    .create_primary_index(&name, CreatePrimaryQueryIndexOptions::default())
-                    .await
-                {
-                    Ok(_result) => {
-                        tracing::debug!("primary index created");
-                    }
-                    Err(e) => tracing::error!("got error! {}", e),
-                }
-            }
+        let transactions = ids
+            .into_iter()
+            .map(|id| {
+                serde_json::json!({
+                    "id": id,
+                    "user_id": 42,
+                    "amount": 100.0,
+                    "transaction_type": transaction_type
+                })
+            })
+            .collect::<Vec<_>>();
+        app_data.scope.seed(collection_name, &transactions).await;
+    }
+
+    // When
+    let mut ids = Vec::new();
+    let mut after: Option<String> = None;
+    loop {
+        let url = match &after {
+            Some(after) => format!(
+                "{}/v2/transactions?limit=1&after={}",
+                &app_data.address, after
+            ),
+            None => format!("{}/v2/transactions?limit=1", &app_data.address),
+        };
+        let page: Page<Transaction> = client
+            .get(&url)
+            .header(API_KEY_HEADER, API_KEY)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .json()
+            .await
+            .expect("Failed to deserialize response");
+
+        ids.extend(page.data.iter().map(|t| t.id));
+        after = page.pagination.next;
+        if after.is_none() || ids.len() > 3 {
+            break;
         }
+    }
 
-        Err(e) => {
-            tracing::error!("got error! {}", e)
+    // Then
+    assert_eq!(vec![1, 1, 2], ids);
+}
+
+#[actix_web::test]
+async fn graphql_queries_users_and_filtered_transactions() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let transactions =
+        [(1, 42, 100.0), (2, 42, 50.0), (3, 7, 80.0)].map(|(id, user_id, amount)| {
+            serde_json::json!({
+                "id": id,
+                "user_id": user_id,
+                "amount": amount,
+                "transaction_type": "Deposit"
+            })
+        });
+    app_data.scope.seed("deposit", &transactions).await;
+
+    let query = r#"{
+        user(id: "42") { balance transactions(first: 10) { edges { node { id } } } }
+        transactions(type: DEPOSIT, amountRange: { min: 60 }, first: 10) {
+            edges { node { id userId } }
+            pageInfo { hasNextPage }
         }
-    };
+    }"#;
+
+    // When
+    let response: serde_json::Value = client
+        .post(&format!("{}/graphql", &app_data.address))
+        .header(API_KEY_HEADER, API_KEY)
+        .json(&serde_json::json!({ "query": query }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .expect("Failed to deserialize response");
+
+    // Then
+    assert!(response.get("errors").is_none(), "{}", response);
+    let data = &response["data"];
+    assert_eq!(150.0, data["user"]["balance"]);
+    assert_eq!(
+        serde_json::json!([{ "node": { "id": "1" } }, { "node": { "id": "2" } }]),
+        data["user"]["transactions"]["edges"]
+    );
+    assert_eq!(
+        serde_json::json!([
+            { "node": { "id": "1", "userId": "42" } },
+            { "node": { "id": "3", "userId": "7" } }
+        ]),
+        data["transactions"]["edges"]
+    );
+    assert_eq!(false, data["transactions"]["pageInfo"]["hasNextPage"]);
+}
+
+#[actix_web::test]
+async fn graphql_rejects_queries_beyond_depth_and_complexity() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let queries = [
+        (
+            "{ transactions(first: 1) { edges { node { user { transactions(first: 1) \
+            { edges { node { user { transactions(first: 1) { edges { node { id } } } } } } } } } } } }",
+            "Query is nested too deep.",
+        ),
+        (
+            "{ transactions(first: 1000) { edges { node { id userId amount type } } } }",
+            "Query is too complex.",
+        ),
+        (
+            "{ transactions(first: 100) { edges { node { user { balance } } } } }",
+            "Query is too complex.",
+        ),
+    ];
+
+    for (query, message) in queries {
+        // When
+        let response: serde_json::Value = client
+            .post(&format!("{}/graphql", &app_data.address))
+            .header(API_KEY_HEADER, API_KEY)
+            .json(&serde_json::json!({ "query": query }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .json()
+            .await
+            .expect("Failed to deserialize response");
+
+        // Then
+        assert_eq!(message, response["errors"][0]["message"], "{}", query);
+        assert!(response["data"].is_null());
+    }
+}
+
+// Every handler has to be documented, and every documented operation has to exist and
+// answer with one of its documented statuses
+#[actix_web::test]
+async fn openapi_spec_matches_handlers() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let spec: serde_json::Value = client
+        .get(&format!("{}/openapi.json", &app_data.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .expect("Failed to deserialize the OpenAPI document");
+    let paths = spec["paths"].as_object().expect("Spec without paths");
+
+    // Versioned routes are registered in their scope, so without the version prefix
+    let patterns = handler_patterns();
+    let documented = ApiDoc::openapi().paths.paths;
+    for pattern in &patterns {
+        assert!(
+            documented.keys().any(|path| unversioned(path) == pattern),
+            "Undocumented handler {}",
+            pattern
+        );
+    }
+    for path in documented.keys() {
+        assert!(
+            patterns.iter().any(|pattern| pattern == unversioned(path)),
+            "No handler for documented path {}",
+            path
+        );
+    }
+
+    for (path, item) in paths {
+        let url = path.replace("{type}", "deposit").replace("{user_id}", "42");
+        assert!(
+            !url.contains('{'),
+            "No sample value for the parameters of {}",
+            path
+        );
+
+        for (method, operation) in item.as_object().unwrap() {
+            // When
+            let request = match method.as_str() {
+                "get" => client.get(&format!("{}{}", &app_data.address, url)),
+                "post" => client
+                    .post(&format!("{}{}", &app_data.address, url))
+                    .json(&serde_json::json!({ "query": "{ __typename }" })),
+                _ => panic!("Undocumented method {} of {}", method, path),
+            };
+            let response = request
+                .header(API_KEY_HEADER, API_KEY)
+                .send()
+                .await
+                .expect("Failed to execute request.");
+
+            // Then
+            let status = response.status().as_u16().to_string();
+            assert!(
+                operation["responses"].get(&status).is_some(),
+                "{} {} answered with undocumented status {}",
+                method,
+                path,
+                status
+            );
+        }
+    }
+}
+
+// actix-web keeps its resource map private, so the patterns are read from the route
+// macros of the handlers
+fn handler_patterns() -> Vec<String> {
+    let mut patterns = Vec::new();
+    for entry in std::fs::read_dir("src/routes").expect("Failed to list route modules") {
+        let source = std::fs::read_to_string(entry.expect("Failed to read route module").path())
+            .expect("Failed to read route module");
+        for line in source.lines() {
+            let pattern = ["#[get(\"", "#[post(\"", "#[route(\""]
+                .iter()
+                .find_map(|prefix| line.trim().strip_prefix(prefix));
+            if let Some(pattern) = pattern {
+                patterns.push(pattern.split('"').next().unwrap().to_string());
+            }
+        }
+    }
+    assert!(!patterns.is_empty());
+
+    patterns
+}
+
+fn unversioned(path: &str) -> &str {
+    ["/v1", "/v2"]
+        .iter()
+        .find_map(|version| path.strip_prefix(version))
+        .unwrap_or(path)
+}
+
+#[actix_web::test]
+async fn request_span_continues_incoming_trace() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let trace_id = format!("{:032x}", rand::thread_rng().gen::<u128>());
+    let traceparent = format!("00-{}-00f067aa0ba902b7-01", trace_id);
+
+    // When
+    let response = client
+        .get(&format!("{}/", &app_data.address))
+        .header("traceparent", traceparent)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Then
+    assert!(response.status().is_success());
+
+    // The request span is exported once it closes, shortly after the response is sent
+    let mut exported = false;
+    for _ in 0..10 {
+        exported = SPAN_EXPORTER
+            .get_finished_spans()
+            .expect("Failed to read exported spans")
+            .iter()
+            .any(|span| {
+                span.span_context.trace_id().to_string() == trace_id && span.name == "GET /"
+            });
+        if exported {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert!(exported);
+}
+
+#[actix_web::test]
+async fn metrics_are_recorded_per_route() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    client
+        .get(&format!("{}/", &app_data.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // When
+    let response = client
+        .get(&format!("{}/metrics", &app_data.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Then
+    assert_eq!(200, response.status().as_u16());
+
+    let body = response.text().await.expect("Failed to read response body");
+    assert!(body.contains(
+        r#"transactions_service_http_requests_total{method="GET",route="/",status="200"}"#
+    ));
+}
+
+#[actix_web::test]
+async fn get_transactions_returns_empty_json_when_no_rows() {
+    // Given
+    let client = reqwest::Client::new();
+    let app_data = spawn_app().await;
+    app_data.scope.seed("withdrawal", &[]).await;
+
+    // When
+    let response = client
+        .get(&format!("{}/v1/transactions/withdrawal", &app_data.address))
+        .header(API_KEY_HEADER, API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Then
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_web::test]
+async fn get_transactions_by_type_returns_a_transactions_from_db() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let transaction: serde_json::Value = serde_json::from_str(r#"{"id":1447241290163152320,"user_id":18107235828171665340,"amount":678.7329504848955,"transaction_type":"Withdrawal"}"#).expect("Error deserializing the message");
+    app_data.scope.seed("withdrawal", &[transaction]).await;
+
+    // When
+    let response = client
+        .get(&format!("{}/v1/transactions/withdrawal", &app_data.address))
+        .header(API_KEY_HEADER, API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Then
+    assert_eq!(200, response.status().as_u16());
+
+    let response_body: Vec<Transaction> = response
+        .json()
+        .await
+        .expect("Failed to deserialize response");
+
+    assert_eq!(1, response_body.len());
+}
+
+#[actix_web::test]
+async fn get_user_balance_returns_deposits_minus_withdrawals() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    app_data.scope.seed("bet", &[]).await;
+    app_data.scope.seed("trade", &[]).await;
+    app_data
+        .scope
+        .seed(
+            "deposit",
+            &[serde_json::json!({
+                "id": 1,
+                "user_id": 42,
+                "amount": 100.0,
+                "transaction_type": "Deposit"
+            })],
+        )
+        .await;
+    app_data
+        .scope
+        .seed(
+            "withdrawal",
+            &[serde_json::json!({
+                "id": 2,
+                "user_id": 42,
+                "amount": 30.0,
+                "transaction_type": "Withdrawal"
+            })],
+        )
+        .await;
+
+    // When
+    let response = client
+        .get(&format!("{}/v1/users/42/balance", &app_data.address))
+        .header(API_KEY_HEADER, API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Then
+    assert_eq!(200, response.status().as_u16());
+
+    let response_body: UserBalance = response
+        .json()
+        .await
+        .expect("Failed to deserialize response");
+
+    assert_eq!(70.0, response_body.balance);
+}
+
+#[actix_web::test]
+async fn get_user_balance_sums_types_across_monthly_collections() {
+    // Given
+    let app_data = spawn_app_with_layout(CollectionLayout::Monthly).await;
+
+    let collection_names = ["deposit_2024_01", "deposit_2024_02", "deposit_undated"];
+    for (id, collection_name) in collection_names.into_iter().enumerate() {
+        let transaction = serde_json::json!({
+            "id": id,
+            "user_id": 42,
+            "amount": 100.0,
+            "transaction_type": "Deposit"
+        });
+        app_data.scope.seed(collection_name, &[transaction]).await;
+    }
+
+    // When
+    let response = reqwest::Client::new()
+        .get(&format!("{}/v1/users/42/balance", &app_data.address))
+        .header(API_KEY_HEADER, API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Then
+    assert_eq!(200, response.status().as_u16());
+
+    let response_body: UserBalance = response
+        .json()
+        .await
+        .expect("Failed to deserialize response");
+
+    assert_eq!(300.0, response_body.balance);
+    assert_eq!(1, response_body.totals.len());
+    assert_eq!(3, response_body.totals[0].count);
+}
+
+#[actix_web::test]
+async fn get_transaction_stats_aggregates_by_each_grouping() {
+    // Given
+    let app_data = spawn_app().await;
+
+    // 2024-01-01T10:15:00Z, 2024-01-01T11:30:00Z, 2024-01-02T10:00:00Z and 2024-02-01T00:00:00Z
+    let transactions = [
+        (1, 42, 100.0, 1_704_104_100_000u64, TransactionType::Bet),
+        (2, 42, 50.0, 1_704_108_600_000, TransactionType::Deposit),
+        (3, 42, 100.0, 1_704_108_600_000, TransactionType::Deposit),
+        (4, 7, 30.0, 1_704_189_600_000, TransactionType::Deposit),
+        (5, 7, 20.0, 1_706_745_600_000, TransactionType::Bet),
+    ];
+    // Every type gets its collection, also those without transactions
+    for collection_type in TransactionType::ALL {
+        let documents = transactions
+            .iter()
+            .filter(|(.., transaction_type)| transaction_type == collection_type)
+            .map(|(id, user_id, amount, timestamp, transaction_type)| {
+                serde_json::json!({
+                    "id": id,
+                    "user_id": user_id,
+                    "amount": amount,
+                    "transaction_type": transaction_type,
+                    "timestamp": timestamp
+                })
+            })
+            .collect::<Vec<_>>();
+        app_data
+            .scope
+            .seed(collection_type.collection_name(), &documents)
+            .await;
+    }
+
+    // When
+    let ungrouped = get_transaction_stats(&app_data.address, "").await;
+    let by_user = get_transaction_stats(&app_data.address, "?group_by=user").await;
+    let by_hour = get_transaction_stats(&app_data.address, "?group_by=hour").await;
+    let by_day = get_transaction_stats(&app_data.address, "?group_by=day").await;
+    let by_month = get_transaction_stats(&app_data.address, "?group_by=month").await;
+
+    // Then
+    assert_eq!(2, ungrouped.len());
+    let bets = &ungrouped[0];
+    assert_eq!("bet", bets.transaction_type.to_string());
+    assert_eq!(
+        (2, 120.0, 20.0, 100.0),
+        (bets.count, bets.sum, bets.min, bets.max)
+    );
+    let deposits = &ungrouped[1];
+    assert_eq!("deposit", deposits.transaction_type.to_string());
+    assert_eq!(None, deposits.user_id);
+    assert_eq!(None, deposits.bucket);
+    assert_eq!(
+        (3, 180.0, 30.0, 100.0, 60.0),
+        (
+            deposits.count,
+            deposits.sum,
+            deposits.min,
+            deposits.max,
+            deposits.avg
+        )
+    );
+    // Nearest rank of 30, 50 and 100
+    assert_eq!(
+        (50.0, 100.0, 100.0, 100.0),
+        (deposits.p50, deposits.p90, deposits.p95, deposits.p99)
+    );
+
+    assert_eq!(
+        vec![
+            ("bet".to_string(), Some(7), None, 1, 20.0),
+            ("bet".to_string(), Some(42), None, 1, 100.0),
+            ("deposit".to_string(), Some(7), None, 1, 30.0),
+            ("deposit".to_string(), Some(42), None, 2, 150.0),
+        ],
+        summarize(&by_user)
+    );
+
+    assert_eq!(
+        vec![
+            (
+                "bet".to_string(),
+                None,
+                Some("2024-01-01T10:00:00Z"),
+                1,
+                100.0
+            ),
+            (
+                "bet".to_string(),
+                None,
+                Some("2024-02-01T00:00:00Z"),
+                1,
+                20.0
+            ),
+            (
+                "deposit".to_string(),
+                None,
+                Some("2024-01-01T11:00:00Z"),
+                2,
+                150.0
+            ),
+            (
+                "deposit".to_string(),
+                None,
+                Some("2024-01-02T10:00:00Z"),
+                1,
+                30.0
+            ),
+        ],
+        summarize(&by_hour)
+    );
+
+    assert_eq!(
+        vec![
+            (
+                "bet".to_string(),
+                None,
+                Some("2024-01-01T00:00:00Z"),
+                1,
+                100.0
+            ),
+            (
+                "bet".to_string(),
+                None,
+                Some("2024-02-01T00:00:00Z"),
+                1,
+                20.0
+            ),
+            (
+                "deposit".to_string(),
+                None,
+                Some("2024-01-01T00:00:00Z"),
+                2,
+                150.0
+            ),
+            (
+                "deposit".to_string(),
+                None,
+                Some("2024-01-02T00:00:00Z"),
+                1,
+                30.0
+            ),
+        ],
+        summarize(&by_day)
+    );
+
+    assert_eq!(
+        vec![
+            (
+                "bet".to_string(),
+                None,
+                Some("2024-01-01T00:00:00Z"),
+                1,
+                100.0
+            ),
+            (
+                "bet".to_string(),
+                None,
+                Some("2024-02-01T00:00:00Z"),
+                1,
+                20.0
+            ),
+            (
+                "deposit".to_string(),
+                None,
+                Some("2024-01-01T00:00:00Z"),
+                3,
+                180.0
+            ),
+        ],
+        summarize(&by_month)
+    );
+}
+
+#[actix_web::test]
+async fn get_transaction_stats_aggregates_types_across_monthly_collections() {
+    // Given
+    let app_data = spawn_app_with_layout(CollectionLayout::Monthly).await;
+
+    let deposit = |id: u64, user_id: u64, amount: f64, timestamp: u64| {
+        serde_json::json!({
+            "id": id,
+            "user_id": user_id,
+            "amount": amount,
+            "transaction_type": "Deposit",
+            "timestamp": timestamp
+        })
+    };
+
+    // January and February of deposits, a withdrawal without a timestamp
+    app_data
+        .scope
+        .seed(
+            "deposit_2024_01",
+            &[deposit(1, 42, 30.0, 1_704_104_100_000)],
+        )
+        .await;
+    app_data
+        .scope
+        .seed(
+            "deposit_2024_02",
+            &[
+                deposit(2, 42, 100.0, 1_706_745_600_000),
+                deposit(3, 7, 50.0, 1_706_745_600_000),
+            ],
+        )
+        .await;
+    app_data
+        .scope
+        .seed(
+            "withdrawal_undated",
+            &[serde_json::json!({
+                "id": 4,
+                "user_id": 42,
+                "amount": 20.0,
+                "transaction_type": "Withdrawal"
+            })],
+        )
+        .await;
+
+    // When
+    let ungrouped = get_transaction_stats(&app_data.address, "").await;
+    let by_user = get_transaction_stats(&app_data.address, "?group_by=user").await;
+
+    // Then
+    assert_eq!(2, ungrouped.len());
+    let deposits = &ungrouped[0];
+    assert_eq!("deposit", deposits.transaction_type.to_string());
+    assert_eq!(
+        (3, 180.0, 30.0, 100.0, 60.0, 50.0),
+        (
+            deposits.count,
+            deposits.sum,
+            deposits.min,
+            deposits.max,
+            deposits.avg,
+            deposits.p50
+        )
+    );
+    assert_eq!("withdrawal", ungrouped[1].transaction_type.to_string());
+    assert_eq!(1, ungrouped[1].count);
+
+    assert_eq!(
+        vec![
+            ("deposit".to_string(), Some(7), None, 1, 50.0),
+            ("deposit".to_string(), Some(42), None, 2, 130.0),
+            ("withdrawal".to_string(), Some(42), None, 1, 20.0),
+        ],
+        summarize(&by_user)
+    );
+}
+
+// Sorted by type, user and bucket, the order of the groups is not part of the API
+async fn get_transaction_stats(address: &str, query: &str) -> Vec<TransactionStats> {
+    let response = reqwest::Client::new()
+        .get(&format!("{}/v1/transactions/stats{}", address, query))
+        .header(API_KEY_HEADER, API_KEY)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16(), "{}", query);
+
+    let mut stats: Vec<TransactionStats> = response
+        .json()
+        .await
+        .expect("Failed to deserialize response");
+    stats.sort_by_key(|stats| {
+        (
+            stats.transaction_type.to_string(),
+            stats.user_id,
+            stats.bucket.clone(),
+        )
+    });
+    stats
+}
+
+fn summarize(stats: &[TransactionStats]) -> Vec<(String, Option<u64>, Option<&str>, u64, f64)> {
+    stats
+        .iter()
+        .map(|stats| {
+            (
+                stats.transaction_type.to_string(),
+                stats.user_id,
+                stats.bucket.as_deref(),
+                stats.count,
+                stats.sum,
+            )
+        })
+        .collect()
+}
+
+#[actix_web::test]
+async fn user_tokens_only_see_transactions_of_their_subject() {
+    // Given
+    let app_data = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let transactions = [(1, 42), (2, 43)].map(|(id, user_id)| {
+        serde_json::json!({
+            "id": id,
+            "user_id": user_id,
+            "amount": 100.0,
+            "transaction_type": "Deposit"
+        })
+    });
+    app_data.scope.seed("deposit", &transactions).await;
+
+    let token = jwt("42", "transactions:read", JWT_SECRET);
+
+    // When
+    let own = client
+        .get(&format!("{}/v1/transactions/deposit", &app_data.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let other = client
+        .get(&format!("{}/v1/users/43/transactions", &app_data.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Then
+    assert_eq!(200, own.status().as_u16());
+    assert_eq!(200, other.status().as_u16());
+
+    let own: Vec<Transaction> = own.json().await.expect("Failed to deserialize response");
+    let other: Vec<Transaction> = other.json().await.expect("Failed to deserialize response");
+
+    assert_eq!(1, own.len());
+    assert_eq!(42, own[0].user_id);
+    assert!(other.is_empty());
+}
+
+// Concurrent runs, e.g. the consumer starting while `migrate` runs, apply every migration once
+#[actix_web::test]
+async fn concurrent_migration_runs_apply_every_migration_once() {
+    // Given
+    let app_data = spawn_app().await;
+    let con = &app_data.connection_data;
+    let schema = Schema {
+        bucket_name: con.bucket_name.clone(),
+        scope_name: con.scope_name.clone(),
+        collections: vec!["deposit".to_string()],
+    };
+    provision(&con.cluster, &schema)
+        .await
+        .expect("Schema provisioning failed");
+
+    // When
+    let (first, second) = futures::join!(
+        run_migrations(&con.cluster, &schema),
+        run_migrations(&con.cluster, &schema)
+    );
+    let rerun = run_migrations(&con.cluster, &schema)
+        .await
+        .expect("Schema migration failed");
+
+    // Then
+    let mut applied = first.expect("Schema migration failed");
+    applied.extend(second.expect("Schema migration failed"));
+    applied.sort();
+    assert_eq!(
+        MIGRATIONS
+            .iter()
+            .map(|migration| migration.name)
+            .collect::<Vec<_>>(),
+        applied
+    );
+    assert!(rerun.is_empty());
+}
+
+#[actix_web::test]
+async fn migrations_apply_to_every_collection_of_the_scope() {
+    // Given
+    let app_data = spawn_app().await;
+    let con = &app_data.connection_data;
+
+    // A month before the one provisioned by the monthly layout
+    let previous_month = app_data
+        .scope
+        .seed(
+            "deposit_2023_12",
+            &[serde_json::json!({
+                "id": 1,
+                "user_id": 42,
+                "amount": 100.0,
+                "transaction_type": "Deposit"
+            })],
+        )
+        .await;
+
+    let schema = Schema {
+        bucket_name: con.bucket_name.clone(),
+        scope_name: con.scope_name.clone(),
+        collections: vec!["deposit_2024_01".to_string()],
+    };
+    provision(&con.cluster, &schema)
+        .await
+        .expect("Schema provisioning failed");
+
+    // When
+    run_migrations(&con.cluster, &schema)
+        .await
+        .expect("Schema migration failed");
+
+    // Then
+    let backfilled: Transaction = previous_month
+        .get("1", GetOptions::default())
+        .await
+        .expect("Error getting transaction")
+        .content()
+        .expect("Error deserializing transaction");
+    assert!(backfilled.timestamp.is_some());
+}
+
+// Certificates renewed on disk, e.g. by cert-manager, are served without a restart
+#[actix_web::test]
+async fn tls_certificate_is_reloaded_when_its_files_change() {
+    // Given
+    let directory = std::env::temp_dir().join(format!("tls_{}", Uuid::new_v4().simple()));
+    std::fs::create_dir_all(&directory).expect("Failed to create certificate directory");
+    let settings = TlsSettings {
+        cert: directory.join("cert.pem"),
+        key: directory.join("key.pem"),
+        reload_interval_secs: 1,
+    };
+    install_certificate(&settings, "first");
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let config = server_config(&settings).expect("Invalid TLS configuration");
+    let server = HttpServer::new(|| App::new().route("/", web::get().to(HttpResponse::Ok)))
+        .listen_rustls_0_21(listener, config)
+        .expect("Failed to listen with TLS")
+        .run();
+    tokio::spawn(server);
+
+    let address = format!("https://localhost:{}/", port);
+    assert_eq!(
+        fixture_certificate("first"),
+        served_certificate(&address).await
+    );
+
+    // When
+    install_certificate(&settings, "second");
+
+    // Then
+    let mut served = Vec::new();
+    for _ in 0..10 {
+        sleep(Duration::from_secs(1)).await;
+        served = served_certificate(&address).await;
+        if served == fixture_certificate("second") {
+            break;
+        }
+    }
+    assert_eq!(fixture_certificate("second"), served);
+
+    std::fs::remove_dir_all(&directory).expect("Failed to remove certificate directory");
+}
+
+fn install_certificate(settings: &TlsSettings, fixture: &str) {
+    let fixtures = Path::new("tests/fixtures");
+    std::fs::copy(fixtures.join(format!("{}.pem", fixture)), &settings.cert)
+        .expect("Failed to write certificate");
+    std::fs::copy(fixtures.join(format!("{}.key", fixture)), &settings.key)
+        .expect("Failed to write key");
+}
+
+fn fixture_certificate(fixture: &str) -> Vec<u8> {
+    let pem = std::fs::read(format!("tests/fixtures/{}.pem", fixture))
+        .expect("Failed to read certificate fixture");
+    rustls_pemfile::certs(&mut pem.as_slice())
+        .expect("Invalid certificate fixture")
+        .remove(0)
+}
+
+// A new client per call, so every request makes a new handshake
+async fn served_certificate(address: &str) -> Vec<u8> {
+    let response = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .tls_info(true)
+        .build()
+        .expect("Failed to build client")
+        .get(address)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    response
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|tls_info| tls_info.peer_certificate())
+        .expect("No peer certificate")
+        .to_vec()
+}
+
+async fn spawn_app() -> TestApp {
+    spawn_app_with_layout(CollectionLayout::PerType).await
+}
+
+async fn spawn_app_with_layout(layout: CollectionLayout) -> TestApp {
+    Lazy::force(&TRACING);
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind to random port");
+    let port = listener.local_addr().unwrap().port();
+    let address = format!("http://127.0.0.1:{}", port);
+
+    let mut configuration =
+        get_configuration_for(Environment::Test).expect("Failed to read configuration.");
+    // Every test gets a scope of its own, dropped again by `TestScope`
+    configuration.database.scope_name = format!("test_{}", Uuid::new_v4().simple());
+    configuration.database.layout = layout;
+
+    let connection_data = CouchbaseConnection::new(&configuration.database);
+    let authenticator =
+        Authenticator::new(&configuration.auth).expect("Invalid auth configuration.");
+    let rate_limiter =
+        RateLimiter::new(&configuration.rate_limit).expect("Invalid rate limit configuration.");
+    let schema = build_schema(connection_data.clone(), &configuration.graphql);
+    create_scope(&connection_data).await;
+    let scope = TestScope {
+        cluster: connection_data.cluster.clone(),
+        bucket_name: connection_data.bucket_name.clone(),
+        scope_name: connection_data.scope_name.clone(),
+    };
+
+    let server = transactions_service::run(
+        listener,
+        connection_data.clone(),
+        authenticator,
+        rate_limiter,
+        schema,
+        None,
+    )
+    .await
+    .expect("Server initialization failed.");
+
+    tokio::spawn(server);
+
+    TestApp {
+        address,
+        connection_data,
+        scope,
+    }
+}
+
+fn jwt(subject: &str, scope: &str, secret: &str) -> String {
+    let claims = Claims {
+        sub: subject.to_string(),
+        scope: scope.to_string(),
+        exp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time is before unix epoch")
+            .as_secs()
+            + 600,
+    };
+
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .expect("Failed to encode token")
+}
+
+async fn create_scope(con: &CouchbaseConnection) {
+    let bucket = con.cluster.bucket(&con.bucket_name);
+    let mgr = bucket.collections();
+
+    match mgr
+        .create_scope(&con.scope_name, CreateScopeOptions::default())
+        .await
+    {
+        Ok(_result) => {
+            tracing::debug!("Scope created");
+        }
+        Err(e) => tracing::debug!("Create scope error: {}", e),
+    }
+}
+
+// Migrations are recorded in the bucket's default collection, which outlives the scope
+async fn remove_migration_records(cluster: &Cluster, bucket_name: &str, scope_name: &str) {
+    let collection = cluster.bucket(bucket_name).default_collection();
+
+    for migration in MIGRATIONS {
+        match collection
+            .remove(
+                migration_document(scope_name, migration.name),
+                RemoveOptions::default(),
+            )
+            .await
+        {
+            Ok(_) | Err(CouchbaseError::DocumentNotFound { .. }) => {}
+            Err(e) => tracing::error!("Error removing migration record: {:?}", e),
+        }
+    }
+}
+
+async fn drop_scope(cluster: &Cluster, bucket_name: &str, scope_name: &str) {
+    let bucket = cluster.bucket(bucket_name);
+    let mgr = bucket.collections();
+
+    match mgr
+        .drop_scope(scope_name, DropScopeOptions::default())
+        .await
+    {
+        Ok(_) => {
+            tracing::debug!("{} scope deleted", scope_name);
+        }
+        Err(e) => {
+            tracing::error!("Error deleting scope: {:?}", e)
+        }
+    }
 }
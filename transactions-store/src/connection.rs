@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+
+// Left as they are in the truststore path so it stays readable in logs
+const PATH: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+// Connects with couchbases://, `port` has to be a TLS port of the cluster, e.g. 18091
+#[derive(Debug, Deserialize, Clone)]
+pub struct DatabaseTlsSettings {
+    // PEM encoded CA certificate the cluster certificate is verified against
+    pub ca_cert: PathBuf,
+}
+
+pub fn connection_string(host: &str, port: u16, tls: Option<&DatabaseTlsSettings>) -> String {
+    match tls {
+        Some(tls) => format!(
+            "couchbases://{}:{}?truststorepath={}",
+            host,
+            port,
+            utf8_percent_encode(&tls.ca_cert.to_string_lossy(), PATH)
+        ),
+        None => format!("couchbase://{}:{}", host, port),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_connections_use_couchbase_scheme() {
+        assert_eq!(
+            "couchbase://127.0.0.1:8091",
+            connection_string("127.0.0.1", 8091, None)
+        );
+    }
+
+    #[test]
+    fn tls_connections_pass_the_encoded_truststore_path() {
+        let tls = DatabaseTlsSettings {
+            ca_cert: PathBuf::from("/run/secrets/couchbase ca&v2=1.pem"),
+        };
+
+        assert_eq!(
+            "couchbases://db.example.com:18091?truststorepath=/run/secrets/couchbase%20ca%26v2%3D1.pem",
+            connection_string("db.example.com", 18091, Some(&tls))
+        );
+    }
+}
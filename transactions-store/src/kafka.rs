@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use rdkafka::ClientConfig;
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+
+// The `kafka` section of the configuration of the Event Producer and the Event Consumer
+#[derive(Debug, Deserialize)]
+pub struct KafkaSettings {
+    pub bootstrap_servers: String,
+    #[serde(default)]
+    pub security_protocol: SecurityProtocol,
+    // Required by the sasl_* protocols
+    pub sasl: Option<SaslSettings>,
+    #[serde(default)]
+    pub ssl: SslSettings,
+    // Passed to librdkafka as they are, e.g. `message.timeout.ms`. Properties a service sets
+    // on the returned config itself, like the `group.id` of the consumer, replace them.
+    #[serde(default)]
+    pub properties: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityProtocol {
+    #[default]
+    Plaintext,
+    Ssl,
+    SaslPlaintext,
+    SaslSsl,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SaslMechanism {
+    #[serde(rename = "PLAIN")]
+    Plain,
+    #[serde(rename = "SCRAM-SHA-256")]
+    ScramSha256,
+    #[serde(rename = "SCRAM-SHA-512")]
+    ScramSha512,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SaslSettings {
+    pub mechanism: SaslMechanism,
+    pub username: String,
+    // Either of them is required, the file takes precedence
+    pub password: Option<Secret<String>>,
+    // File holding the password, e.g. a docker or kubernetes secret mount
+    pub password_file: Option<PathBuf>,
+}
+
+// PEM files, the CA verifies the brokers, certificate and key authenticate the client
+#[derive(Debug, Deserialize, Default)]
+pub struct SslSettings {
+    pub ca_location: Option<PathBuf>,
+    pub certificate_location: Option<PathBuf>,
+    pub key_location: Option<PathBuf>,
+    pub key_password: Option<Secret<String>>,
+    pub key_password_file: Option<PathBuf>,
+}
+
+impl SecurityProtocol {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SecurityProtocol::Plaintext => "plaintext",
+            SecurityProtocol::Ssl => "ssl",
+            SecurityProtocol::SaslPlaintext => "sasl_plaintext",
+            SecurityProtocol::SaslSsl => "sasl_ssl",
+        }
+    }
+
+    fn uses_sasl(&self) -> bool {
+        matches!(
+            self,
+            SecurityProtocol::SaslPlaintext | SecurityProtocol::SaslSsl
+        )
+    }
+}
+
+impl SaslMechanism {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            SaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
+}
+
+impl KafkaSettings {
+    // Called once after deserializing, reads the secret files and rejects settings
+    // librdkafka would only fail on when connecting
+    pub fn load(&mut self) -> Result<(), String> {
+        if self.security_protocol.uses_sasl() && self.sasl.is_none() {
+            return Err(format!(
+                "Security protocol {} requires kafka.sasl",
+                self.security_protocol.as_str()
+            ));
+        }
+
+        if let Some(sasl) = &mut self.sasl {
+            match &sasl.password_file {
+                Some(password_file) => sasl.password = Some(read_secret(password_file)?),
+                None if sasl.password.is_none() => {
+                    return Err("kafka.sasl requires password or password_file".to_string())
+                }
+                None => {}
+            }
+        }
+        if let Some(key_password_file) = &self.ssl.key_password_file {
+            self.ssl.key_password = Some(read_secret(key_password_file)?);
+        }
+
+        Ok(())
+    }
+
+    pub fn client_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::new();
+        config
+            .set("bootstrap.servers", &self.bootstrap_servers)
+            .set("security.protocol", self.security_protocol.as_str());
+
+        if let Some(sasl) = &self.sasl {
+            config
+                .set("sasl.mechanism", sasl.mechanism.as_str())
+                .set("sasl.username", &sasl.username);
+            if let Some(password) = &sasl.password {
+                config.set("sasl.password", password.expose_secret());
+            }
+        }
+
+        if let Some(ca_location) = &self.ssl.ca_location {
+            config.set("ssl.ca.location", ca_location.to_string_lossy());
+        }
+        if let Some(certificate_location) = &self.ssl.certificate_location {
+            config.set(
+                "ssl.certificate.location",
+                certificate_location.to_string_lossy(),
+            );
+        }
+        if let Some(key_location) = &self.ssl.key_location {
+            config.set("ssl.key.location", key_location.to_string_lossy());
+        }
+        if let Some(key_password) = &self.ssl.key_password {
+            config.set("ssl.key.password", key_password.expose_secret());
+        }
+
+        for (key, value) in &self.properties {
+            config.set(key, value);
+        }
+
+        config
+    }
+}
+
+fn read_secret(path: &Path) -> Result<Secret<String>, String> {
+    std::fs::read_to_string(path)
+        .map(|secret| Secret::new(secret.trim_end().to_string()))
+        .map_err(|e| format!("Failed to read secret from {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(security_protocol: SecurityProtocol) -> KafkaSettings {
+        KafkaSettings {
+            bootstrap_servers: "broker-1:9093,broker-2:9093".to_string(),
+            security_protocol,
+            sasl: None,
+            ssl: SslSettings::default(),
+            properties: HashMap::new(),
+        }
+    }
+
+    fn sasl(password: Option<&str>, password_file: Option<PathBuf>) -> SaslSettings {
+        SaslSettings {
+            mechanism: SaslMechanism::ScramSha512,
+            username: "event-consumer".to_string(),
+            password: password.map(|password| Secret::new(password.to_string())),
+            password_file,
+        }
+    }
+
+    fn secret_file(name: &str, secret: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+        std::fs::write(&path, secret).unwrap();
+        path
+    }
+
+    #[test]
+    fn plaintext_sets_only_servers_and_protocol() {
+        let config = settings(SecurityProtocol::Plaintext).client_config();
+
+        assert_eq!(
+            Some("broker-1:9093,broker-2:9093"),
+            config.get("bootstrap.servers")
+        );
+        assert_eq!(Some("plaintext"), config.get("security.protocol"));
+        assert_eq!(None, config.get("sasl.mechanism"));
+        assert_eq!(None, config.get("ssl.ca.location"));
+    }
+
+    #[test]
+    fn sasl_ssl_sets_credentials_and_certificates() {
+        let mut settings = settings(SecurityProtocol::SaslSsl);
+        settings.sasl = Some(sasl(Some("password"), None));
+        settings.ssl = SslSettings {
+            ca_location: Some(PathBuf::from("/run/secrets/kafka_ca.pem")),
+            certificate_location: Some(PathBuf::from("/run/secrets/kafka_client.pem")),
+            key_location: Some(PathBuf::from("/run/secrets/kafka_client.key")),
+            key_password: Some(Secret::new("key-password".to_string())),
+            key_password_file: None,
+        };
+
+        let config = settings.client_config();
+
+        assert_eq!(Some("sasl_ssl"), config.get("security.protocol"));
+        assert_eq!(Some("SCRAM-SHA-512"), config.get("sasl.mechanism"));
+        assert_eq!(Some("event-consumer"), config.get("sasl.username"));
+        assert_eq!(Some("password"), config.get("sasl.password"));
+        assert_eq!(
+            Some("/run/secrets/kafka_ca.pem"),
+            config.get("ssl.ca.location")
+        );
+        assert_eq!(
+            Some("/run/secrets/kafka_client.pem"),
+            config.get("ssl.certificate.location")
+        );
+        assert_eq!(
+            Some("/run/secrets/kafka_client.key"),
+            config.get("ssl.key.location")
+        );
+        assert_eq!(Some("key-password"), config.get("ssl.key.password"));
+    }
+
+    #[test]
+    fn properties_are_passed_as_they_are() {
+        let mut settings = settings(SecurityProtocol::Plaintext);
+        settings.properties = HashMap::from([
+            ("message.timeout.ms".to_string(), "5000".to_string()),
+            ("security.protocol".to_string(), "ssl".to_string()),
+        ]);
+
+        let config = settings.client_config();
+
+        assert_eq!(Some("5000"), config.get("message.timeout.ms"));
+        assert_eq!(Some("ssl"), config.get("security.protocol"));
+    }
+
+    #[test]
+    fn sasl_protocols_require_credentials() {
+        for protocol in [SecurityProtocol::SaslPlaintext, SecurityProtocol::SaslSsl] {
+            assert!(settings(protocol).load().is_err());
+        }
+        for protocol in [SecurityProtocol::Plaintext, SecurityProtocol::Ssl] {
+            assert!(settings(protocol).load().is_ok());
+        }
+    }
+
+    #[test]
+    fn password_files_take_precedence() {
+        let mut settings = settings(SecurityProtocol::SaslSsl);
+        settings.sasl = Some(sasl(
+            Some("password"),
+            Some(secret_file("sasl", "from-file\n")),
+        ));
+        settings.ssl.key_password_file = Some(secret_file("ssl-key", "key-from-file\n"));
+
+        settings.load().unwrap();
+        let config = settings.client_config();
+
+        assert_eq!(Some("from-file"), config.get("sasl.password"));
+        assert_eq!(Some("key-from-file"), config.get("ssl.key.password"));
+    }
+
+    #[test]
+    fn missing_password_files_are_rejected() {
+        let mut settings = settings(SecurityProtocol::SaslSsl);
+        settings.sasl = Some(sasl(
+            None,
+            Some(PathBuf::from("/nonexistent/sasl-password")),
+        ));
+
+        assert!(settings.load().is_err());
+    }
+
+    #[test]
+    fn password_file_alone_is_enough() {
+        let mut settings = settings(SecurityProtocol::SaslSsl);
+        settings.sasl = Some(sasl(None, Some(secret_file("sasl-only", "from-file"))));
+
+        settings.load().unwrap();
+
+        assert_eq!(
+            Some("from-file"),
+            settings.client_config().get("sasl.password")
+        );
+    }
+
+    #[test]
+    fn sasl_without_any_password_is_rejected() {
+        let mut settings = settings(SecurityProtocol::SaslSsl);
+        settings.sasl = Some(sasl(None, None));
+
+        assert!(settings.load().is_err());
+    }
+}
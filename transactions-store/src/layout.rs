@@ -0,0 +1,329 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Datelike};
+use couchbase::{Cluster, CouchbaseResult, QueryOptions};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+
+// Monthly collections of transactions without a valid timestamp, e.g. `bet_undated`
+const UNDATED: &str = "undated";
+
+// Readers of the monthly layout see a new month's collection at most this late
+const COLLECTIONS_TTL: Duration = Duration::from_secs(60);
+
+// Collections of a scope by bucket and scope name, with the time they were looked up
+type CollectionCache = BTreeMap<(String, String), (Instant, Vec<String>)>;
+
+static SCOPE_COLLECTIONS: Mutex<CollectionCache> = Mutex::new(BTreeMap::new());
+
+// Decides in which collections transactions are stored, shared by the writer and the readers
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum CollectionLayout {
+    // A collection per transaction type, named after the type
+    #[default]
+    PerType,
+    // One collection for every type, told apart by the `transaction_type` field
+    Single {
+        collection_name: String,
+    },
+    // A collection per transaction type and month, e.g. `bet_2024_01`,
+    // old months can be dropped as a whole. Transactions without a timestamp go to `bet_undated`.
+    Monthly,
+}
+
+// A collection to read together with the condition selecting the requested types in it,
+// predicates refer to the document as `t`
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub collection_name: String,
+    pub predicate: Option<String>,
+}
+
+impl CollectionLayout {
+    // `timestamp` in milliseconds since epoch
+    pub fn collection_for(&self, type_name: &str, timestamp: Option<u64>) -> String {
+        match self {
+            CollectionLayout::PerType => type_name.to_string(),
+            CollectionLayout::Single { collection_name } => collection_name.clone(),
+            CollectionLayout::Monthly => {
+                match timestamp
+                    .and_then(|timestamp| i64::try_from(timestamp / 1000).ok())
+                    .and_then(|secs| DateTime::from_timestamp(secs, 0))
+                {
+                    Some(date) => monthly_collection_name(type_name, date.year(), date.month()),
+                    None => format!("{}_{}", type_name, UNDATED),
+                }
+            }
+        }
+    }
+
+    // Collections provisioned up front, monthly ones are created by the writer as months go by
+    pub fn initial_collections(&self, type_names: &[String], now: u64) -> Vec<String> {
+        match self {
+            CollectionLayout::Single { collection_name } => vec![collection_name.clone()],
+            _ => type_names
+                .iter()
+                .map(|type_name| self.collection_for(type_name, Some(now)))
+                .collect(),
+        }
+    }
+
+    pub async fn partitions(
+        &self,
+        cluster: &Cluster,
+        bucket_name: &str,
+        scope_name: &str,
+        type_names: &[&str],
+    ) -> CouchbaseResult<Vec<Partition>> {
+        let collections = match self {
+            CollectionLayout::Monthly => {
+                scope_collections(cluster, bucket_name, scope_name).await?
+            }
+            _ => vec![],
+        };
+
+        Ok(self.partitions_in(type_names, collections))
+    }
+
+    // `collections` exist in the scope, only the monthly layout looks at them
+    fn partitions_in(&self, type_names: &[&str], collections: Vec<String>) -> Vec<Partition> {
+        match self {
+            CollectionLayout::PerType => type_names
+                .iter()
+                .map(|type_name| Partition {
+                    collection_name: type_name.to_string(),
+                    predicate: None,
+                })
+                .collect(),
+            CollectionLayout::Single { collection_name } => {
+                let types = type_names
+                    .iter()
+                    .map(|type_name| format!("\"{}\"", type_name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                vec![Partition {
+                    collection_name: collection_name.clone(),
+                    predicate: Some(format!("LOWER(t.transaction_type) IN [{}]", types)),
+                }]
+            }
+            CollectionLayout::Monthly => collections
+                .into_iter()
+                .filter(|collection_name| {
+                    type_names
+                        .iter()
+                        .any(|type_name| is_monthly_collection_of(collection_name, type_name))
+                })
+                .map(|collection_name| Partition {
+                    collection_name,
+                    predicate: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+fn monthly_collection_name(type_name: &str, year: i32, month: u32) -> String {
+    format!("{}_{:04}_{:02}", type_name, year, month)
+}
+
+fn is_monthly_collection_of(collection_name: &str, type_name: &str) -> bool {
+    collection_name
+        .strip_prefix(type_name)
+        .and_then(|suffix| suffix.strip_prefix('_'))
+        .map(|suffix| {
+            let dated = suffix.len() == 7
+                && suffix.chars().enumerate().all(|(i, c)| {
+                    if i == 4 {
+                        c == '_'
+                    } else {
+                        c.is_ascii_digit()
+                    }
+                });
+            dated || suffix == UNDATED
+        })
+        .unwrap_or(false)
+}
+
+// Looked up once per `COLLECTIONS_TTL` instead of on every read
+async fn scope_collections(
+    cluster: &Cluster,
+    bucket_name: &str,
+    scope_name: &str,
+) -> CouchbaseResult<Vec<String>> {
+    let key = (bucket_name.to_string(), scope_name.to_string());
+    if let Some((looked_up, collections)) = SCOPE_COLLECTIONS
+        .lock()
+        .expect("Collection cache lock is poisoned")
+        .get(&key)
+    {
+        if looked_up.elapsed() < COLLECTIONS_TTL {
+            return Ok(collections.clone());
+        }
+    }
+
+    let collections = query_scope_collections(cluster, bucket_name, scope_name).await?;
+    SCOPE_COLLECTIONS
+        .lock()
+        .expect("Collection cache lock is poisoned")
+        .insert(key, (Instant::now(), collections.clone()));
+
+    Ok(collections)
+}
+
+pub(crate) async fn query_scope_collections(
+    cluster: &Cluster,
+    bucket_name: &str,
+    scope_name: &str,
+) -> CouchbaseResult<Vec<String>> {
+    let query = "SELECT RAW k.name FROM system:keyspaces AS k \
+        WHERE k.`bucket` = $bucket AND k.`scope` = $scope";
+    let options = QueryOptions::default()
+        .named_parameters(json!({ "bucket": bucket_name, "scope": scope_name }));
+
+    let mut result = cluster.query(query, options).await?;
+    let mut rows = result.rows::<String>();
+
+    let mut collections = vec![];
+    while let Some(row) = rows.next().await {
+        collections.push(row?);
+    }
+
+    Ok(collections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-01-31T23:59:59.999Z and 2024-02-01T00:00:00Z
+    const END_OF_JANUARY: u64 = 1_706_745_599_999;
+    const START_OF_FEBRUARY: u64 = 1_706_745_600_000;
+
+    fn single() -> CollectionLayout {
+        CollectionLayout::Single {
+            collection_name: "transactions".to_string(),
+        }
+    }
+
+    #[test]
+    fn collections_follow_the_layout() {
+        assert_eq!(
+            "bet",
+            CollectionLayout::PerType.collection_for("bet", Some(END_OF_JANUARY))
+        );
+        assert_eq!("transactions", single().collection_for("bet", None));
+        assert_eq!(
+            "bet_2024_01",
+            CollectionLayout::Monthly.collection_for("bet", Some(END_OF_JANUARY))
+        );
+        assert_eq!(
+            "bet_2024_02",
+            CollectionLayout::Monthly.collection_for("bet", Some(START_OF_FEBRUARY))
+        );
+    }
+
+    #[test]
+    fn transactions_without_a_valid_timestamp_are_undated() {
+        for timestamp in [None, Some(u64::MAX)] {
+            assert_eq!(
+                "bet_undated",
+                CollectionLayout::Monthly.collection_for("bet", timestamp)
+            );
+        }
+    }
+
+    #[test]
+    fn monthly_collections_are_told_apart_by_type() {
+        for collection_name in ["bet_2024_01", "bet_1999_12", "bet_undated"] {
+            assert!(
+                is_monthly_collection_of(collection_name, "bet"),
+                "{}",
+                collection_name
+            );
+        }
+        for collection_name in [
+            "bet",
+            "bet_2024_1",
+            "bet_2024-01",
+            "bet_2024_01_old",
+            "bet_bonus_2024_01",
+            "bonus_2024_01",
+        ] {
+            assert!(
+                !is_monthly_collection_of(collection_name, "bet"),
+                "{}",
+                collection_name
+            );
+        }
+    }
+
+    #[test]
+    fn initial_collections_are_those_of_now() {
+        let type_names = ["bet".to_string(), "fee".to_string()];
+
+        assert_eq!(
+            vec!["bet", "fee"],
+            CollectionLayout::PerType.initial_collections(&type_names, END_OF_JANUARY)
+        );
+        assert_eq!(
+            vec!["transactions"],
+            single().initial_collections(&type_names, END_OF_JANUARY)
+        );
+        assert_eq!(
+            vec!["bet_2024_01", "fee_2024_01"],
+            CollectionLayout::Monthly.initial_collections(&type_names, END_OF_JANUARY)
+        );
+    }
+
+    #[test]
+    fn partitions_select_the_requested_types() {
+        let existing = [
+            "bet_2024_01",
+            "bet_2024_02",
+            "bet_undated",
+            "fee_2024_01",
+            "transactions",
+        ]
+        .map(String::from)
+        .to_vec();
+
+        let per_type = CollectionLayout::PerType.partitions_in(&["bet", "fee"], existing.clone());
+        assert_eq!(
+            vec!["bet", "fee"],
+            per_type
+                .iter()
+                .map(|partition| partition.collection_name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(per_type
+            .iter()
+            .all(|partition| partition.predicate.is_none()));
+
+        let single = single().partitions_in(&["bet", "fee"], existing.clone());
+        assert_eq!(1, single.len());
+        assert_eq!("transactions", single[0].collection_name);
+        assert_eq!(
+            Some(r#"LOWER(t.transaction_type) IN ["bet", "fee"]"#),
+            single[0].predicate.as_deref()
+        );
+
+        let monthly = CollectionLayout::Monthly.partitions_in(&["bet"], existing);
+        assert_eq!(
+            vec!["bet_2024_01", "bet_2024_02", "bet_undated"],
+            monthly
+                .iter()
+                .map(|partition| partition.collection_name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(monthly
+            .iter()
+            .all(|partition| partition.predicate.is_none()));
+    }
+}
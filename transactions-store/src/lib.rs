@@ -0,0 +1,11 @@
+#[cfg(feature = "database")]
+pub mod connection;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "database")]
+pub mod layout;
+#[cfg(feature = "database")]
+pub mod migrations;
+pub mod model;
+#[cfg(feature = "database")]
+pub mod provisioning;
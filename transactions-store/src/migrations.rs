@@ -0,0 +1,182 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use couchbase::{
+    Cluster, Collection, CouchbaseError, CouchbaseResult, GetOptions, InsertOptions, QueryOptions,
+    RemoveOptions, ReplaceOptions,
+};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::{layout::query_scope_collections, provisioning::Schema};
+
+// A claim expires after this long, so a process that died while applying a migration does not
+// block the others for good. Migrations have to be idempotent and finish well within it.
+const CLAIM_TTL: Duration = Duration::from_secs(30 * 60);
+const CLAIM_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+type MigrationFn = for<'a> fn(&'a Cluster, &'a Schema) -> LocalBoxFuture<'a, CouchbaseResult<()>>;
+
+pub struct Migration {
+    pub name: &'static str,
+    up: MigrationFn,
+}
+
+// Append only, applied in order and never edited once released. Indexes are not migrated,
+// provisioning ensures `SECONDARY_INDEXES` on every collection.
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    name: "0001_backfill_timestamps",
+    up: backfill_timestamps,
+}];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MigrationState {
+    Applying,
+    Applied,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MigrationRecord {
+    state: MigrationState,
+    updated_at: u64,
+}
+
+impl MigrationRecord {
+    fn new(state: MigrationState) -> Self {
+        MigrationRecord {
+            state,
+            updated_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+// One document per migration and scope in the bucket's default collection rather than a single
+// `_migrations` log: inserting it claims the migration atomically, so of concurrent runs (e.g. the
+// consumer starting while `migrate` runs) only one applies it and the others wait until it is
+// recorded as applied. A shared log would need a CAS retry loop around every claim and release.
+pub fn migration_document(scope_name: &str, migration_name: &str) -> String {
+    format!("_migration::{}::{}", scope_name, migration_name)
+}
+
+pub async fn run_migrations(
+    cluster: &Cluster,
+    schema: &Schema,
+) -> CouchbaseResult<Vec<&'static str>> {
+    let collection = cluster.bucket(&schema.bucket_name).default_collection();
+    let schema = &Schema {
+        collections: migrated_collections(cluster, schema).await?,
+        ..schema.clone()
+    };
+
+    let mut applied = vec![];
+
+    for migration in MIGRATIONS {
+        let id = migration_document(&schema.scope_name, migration.name);
+        let cas = match claim(&collection, &id).await? {
+            Some(cas) => cas,
+            None => continue,
+        };
+
+        tracing::info!("Applying migration {}", migration.name);
+        if let Err(e) = (migration.up)(cluster, schema).await {
+            // Released so the next run retries the migration right away
+            if let Err(release_error) = collection
+                .remove(&id, RemoveOptions::default().cas(cas))
+                .await
+            {
+                tracing::warn!(
+                    "Failed to release migration {}: {}",
+                    migration.name,
+                    release_error
+                );
+            }
+            return Err(e);
+        }
+
+        // Replacing without an expiry keeps the record for good, the CAS fails if the claim
+        // expired and another run took it over in the meantime
+        collection
+            .replace(
+                &id,
+                MigrationRecord::new(MigrationState::Applied),
+                ReplaceOptions::default().cas(cas),
+            )
+            .await?;
+
+        applied.push(migration.name);
+    }
+
+    Ok(applied)
+}
+
+// CAS of the claim when this run has to apply the migration, `None` once another run applied it
+async fn claim(collection: &Collection, id: &str) -> CouchbaseResult<Option<u64>> {
+    loop {
+        match collection
+            .insert(
+                id,
+                MigrationRecord::new(MigrationState::Applying),
+                InsertOptions::default().expiry(CLAIM_TTL),
+            )
+            .await
+        {
+            Ok(result) => return Ok(Some(result.cas())),
+            Err(CouchbaseError::DocumentExists { .. }) => {}
+            Err(e) => return Err(e),
+        }
+
+        match collection.get(id, GetOptions::default()).await {
+            Ok(result) => {
+                if result.content::<MigrationRecord>()?.state == MigrationState::Applied {
+                    return Ok(None);
+                }
+                tracing::info!("Waiting for migration {} claimed by another run", id);
+                sleep(CLAIM_POLL_INTERVAL).await;
+            }
+            // The claim expired or was released, try to take it over
+            Err(CouchbaseError::DocumentNotFound { .. }) => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Every collection of the scope besides those of `schema`, which under the monthly layout are
+// the current month's only. Older months and undated transactions are migrated as well.
+async fn migrated_collections(cluster: &Cluster, schema: &Schema) -> CouchbaseResult<Vec<String>> {
+    let mut collections =
+        query_scope_collections(cluster, &schema.bucket_name, &schema.scope_name).await?;
+    collections.retain(|collection_name| collection_name != "_default");
+    for collection_name in &schema.collections {
+        if !collections.contains(collection_name) {
+            collections.push(collection_name.clone());
+        }
+    }
+
+    Ok(collections)
+}
+
+// Documents written before transactions carried a timestamp get their last
+// mutation time (the CAS is in nanoseconds since epoch)
+fn backfill_timestamps<'a>(
+    cluster: &'a Cluster,
+    schema: &'a Schema,
+) -> LocalBoxFuture<'a, CouchbaseResult<()>> {
+    Box::pin(async move {
+        for collection_name in &schema.collections {
+            let query = format!(
+                "UPDATE `{}`.`{}`.`{}` AS t \
+                SET t.timestamp = FLOOR(META(t).cas / 1000000) \
+                WHERE t.timestamp IS MISSING",
+                schema.bucket_name, schema.scope_name, collection_name
+            );
+
+            cluster.query(query, QueryOptions::default()).await?;
+        }
+
+        Ok(())
+    })
+}
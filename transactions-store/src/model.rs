@@ -0,0 +1,108 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+// Shared by the producer, the consumer and the service, a new type is added here only
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+pub enum TransactionType {
+    Bet,
+    Trade,
+    Deposit,
+    Withdrawal,
+    Refund,
+    Bonus,
+    Fee,
+    // Types introduced by newer producers deserialize here instead of failing
+    #[serde(other)]
+    Unknown,
+}
+
+impl TransactionType {
+    // Every type is stored in its own collection, queries over all types are generated from this list
+    pub const ALL: &'static [TransactionType] = &[
+        TransactionType::Bet,
+        TransactionType::Trade,
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Refund,
+        TransactionType::Bonus,
+        TransactionType::Fee,
+    ];
+
+    pub fn collection_name(&self) -> &'static str {
+        match self {
+            TransactionType::Bet => "bet",
+            TransactionType::Trade => "trade",
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Refund => "refund",
+            TransactionType::Bonus => "bonus",
+            TransactionType::Fee => "fee",
+            TransactionType::Unknown => "unknown",
+        }
+    }
+
+    // Deposits, refunds and bonuses credit the user's account, unknown types are left out
+    pub fn balance_sign(&self) -> f64 {
+        match self {
+            TransactionType::Deposit | TransactionType::Refund | TransactionType::Bonus => 1.0,
+            TransactionType::Bet
+            | TransactionType::Trade
+            | TransactionType::Withdrawal
+            | TransactionType::Fee => -1.0,
+            TransactionType::Unknown => 0.0,
+        }
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.collection_name())
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TransactionType::ALL
+            .iter()
+            .find(|transaction_type| transaction_type.collection_name() == s)
+            .copied()
+            .ok_or_else(|| format!("{} is not a valid transaction type", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction_type(name: &str) -> TransactionType {
+        serde_json::from_str(&format!("\"{}\"", name)).unwrap()
+    }
+
+    #[test]
+    fn known_types_deserialize_to_their_variant() {
+        assert_eq!(TransactionType::Deposit, transaction_type("Deposit"));
+        assert_eq!(TransactionType::Fee, transaction_type("Fee"));
+    }
+
+    #[test]
+    fn types_of_newer_producers_deserialize_to_unknown() {
+        assert_eq!(TransactionType::Unknown, transaction_type("Cashback"));
+        assert_eq!(TransactionType::Unknown, transaction_type("deposit"));
+    }
+
+    #[test]
+    fn collection_names_parse_back_to_their_type() {
+        for transaction_type in TransactionType::ALL {
+            assert_eq!(
+                Ok(*transaction_type),
+                transaction_type.collection_name().parse()
+            );
+        }
+        assert!("unknown".parse::<TransactionType>().is_err());
+    }
+}
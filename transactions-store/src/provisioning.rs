@@ -0,0 +1,335 @@
+use std::{fmt::Display, future::Future, time::Duration};
+
+use couchbase::{
+    BucketSettingsBuilder, Cluster, CollectionSpec, CouchbaseError, CouchbaseResult,
+    CreateBucketOptions, CreateCollectionOptions, CreatePrimaryQueryIndexOptions,
+    CreateQueryIndexOptions, CreateScopeOptions, GetAllQueryIndexOptions,
+};
+use tokio::time::sleep;
+
+const BUCKET_RAM_QUOTA_MB: u64 = 256;
+
+// Indexes of every transaction collection, ensured whenever a collection is provisioned.
+// Adding one here creates it on the next start, no migration is needed.
+pub const SECONDARY_INDEXES: &[(&str, &[&str])] = &[
+    ("idx_user_id", &["user_id"]),
+    ("idx_timestamp", &["timestamp"]),
+    ("idx_id", &["id"]),
+];
+
+// Freshly created buckets and collections take a moment before they accept further operations,
+// only transient errors are retried
+const RETRY_ATTEMPTS: u32 = 10;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pub bucket_name: String,
+    pub scope_name: String,
+    pub collections: Vec<String>,
+}
+
+pub async fn provision(cluster: &Cluster, schema: &Schema) -> CouchbaseResult<()> {
+    ensure_bucket(cluster, &schema.bucket_name).await?;
+    ensure_scope(cluster, &schema.bucket_name, &schema.scope_name).await?;
+
+    for collection_name in &schema.collections {
+        provision_collection(
+            cluster,
+            &schema.bucket_name,
+            &schema.scope_name,
+            collection_name,
+        )
+        .await?;
+    }
+
+    tracing::info!(
+        "Schema {}.{} is provisioned",
+        schema.bucket_name,
+        schema.scope_name
+    );
+
+    Ok(())
+}
+
+// Also used on its own for collections created while running, e.g. a new month of a monthly layout
+pub async fn provision_collection(
+    cluster: &Cluster,
+    bucket_name: &str,
+    scope_name: &str,
+    collection_name: &str,
+) -> CouchbaseResult<()> {
+    ensure_collection(cluster, bucket_name, scope_name, collection_name).await?;
+    ensure_primary_index(cluster, bucket_name, scope_name, collection_name).await?;
+
+    for (index_name, fields) in SECONDARY_INDEXES {
+        ensure_index(
+            cluster,
+            bucket_name,
+            scope_name,
+            collection_name,
+            index_name,
+            fields,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn ensure_bucket(cluster: &Cluster, bucket_name: &str) -> CouchbaseResult<()> {
+    retry(|| async move {
+        let settings = BucketSettingsBuilder::new(bucket_name)
+            .ram_quota_mb(BUCKET_RAM_QUOTA_MB)
+            .build();
+
+        match cluster
+            .buckets()
+            .create_bucket(settings, CreateBucketOptions::default())
+            .await
+        {
+            Ok(_) => {
+                tracing::info!("Bucket {} created", bucket_name);
+                Ok(())
+            }
+            Err(CouchbaseError::BucketExists { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    })
+    .await
+}
+
+pub async fn ensure_scope(
+    cluster: &Cluster,
+    bucket_name: &str,
+    scope_name: &str,
+) -> CouchbaseResult<()> {
+    retry(|| async move {
+        match cluster
+            .bucket(bucket_name)
+            .collections()
+            .create_scope(scope_name, CreateScopeOptions::default())
+            .await
+        {
+            Ok(_) => {
+                tracing::info!("Scope {} created in {}", scope_name, bucket_name);
+                Ok(())
+            }
+            Err(CouchbaseError::ScopeExists { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    })
+    .await
+}
+
+pub async fn ensure_collection(
+    cluster: &Cluster,
+    bucket_name: &str,
+    scope_name: &str,
+    collection_name: &str,
+) -> CouchbaseResult<()> {
+    retry(|| async move {
+        match cluster
+            .bucket(bucket_name)
+            .collections()
+            .create_collection(
+                CollectionSpec::new(collection_name, scope_name, Duration::from_secs(0)),
+                CreateCollectionOptions::default(),
+            )
+            .await
+        {
+            Ok(_) => {
+                tracing::info!("Collection {} created in {}", collection_name, scope_name);
+                Ok(())
+            }
+            Err(CouchbaseError::CollectionExists { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    })
+    .await
+}
+
+pub async fn ensure_primary_index(
+    cluster: &Cluster,
+    bucket_name: &str,
+    scope_name: &str,
+    collection_name: &str,
+) -> CouchbaseResult<()> {
+    let index_manager = &cluster.query_indexes();
+    let name = &keyspace_name(bucket_name, scope_name, collection_name);
+
+    let exists = retry(|| async move {
+        index_manager
+            .get_all_indexes(name, GetAllQueryIndexOptions::default())
+            .await
+    })
+    .await?
+    .into_iter()
+    .any(|index| index.is_primary());
+
+    if !exists {
+        retry(|| async move {
+            index_manager
+                .create_primary_index(name, CreatePrimaryQueryIndexOptions::default())
+                .await
+        })
+        .await?;
+        tracing::info!("Primary index created on {}", collection_name);
+    }
+
+    Ok(())
+}
+
+pub async fn ensure_index(
+    cluster: &Cluster,
+    bucket_name: &str,
+    scope_name: &str,
+    collection_name: &str,
+    index_name: &str,
+    fields: &[&str],
+) -> CouchbaseResult<()> {
+    let index_manager = &cluster.query_indexes();
+    let name = &keyspace_name(bucket_name, scope_name, collection_name);
+
+    let exists = retry(|| async move {
+        index_manager
+            .get_all_indexes(name, GetAllQueryIndexOptions::default())
+            .await
+    })
+    .await?
+    .into_iter()
+    .any(|index| index.name() == index_name);
+
+    if !exists {
+        retry(|| async move {
+            index_manager
+                .create_index(
+                    name,
+                    index_name,
+                    fields.iter().map(|field| field.to_string()),
+                    CreateQueryIndexOptions::default(),
+                )
+                .await
+        })
+        .await?;
+        tracing::info!("Index {} created on {}", index_name, collection_name);
+    }
+
+    Ok(())
+}
+
+// The index manager wraps the name in backticks, the inner ones address the collection
+pub fn keyspace_name(bucket_name: &str, scope_name: &str, collection_name: &str) -> String {
+    format!("{}`.`{}`.`{}", bucket_name, scope_name, collection_name)
+}
+
+async fn retry<F, Fut, T>(operation: F) -> CouchbaseResult<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = CouchbaseResult<T>>,
+{
+    retry_when(operation, is_transient).await
+}
+
+// Retries while `transient` holds for the error, others are returned right away
+async fn retry_when<F, Fut, T, E>(operation: F, transient: fn(&E) -> bool) -> Result<T, E>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    let mut attempt = 1;
+
+    loop {
+        match operation().await {
+            Err(e) if attempt < RETRY_ATTEMPTS && transient(&e) => {
+                tracing::warn!("Provisioning attempt {} failed: {}", attempt, e);
+                sleep(RETRY_DELAY).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Errors of a cluster that is still starting or of a bucket, scope or collection
+// created a moment ago. Failed authentication or invalid names won't go away by waiting.
+fn is_transient(e: &CouchbaseError) -> bool {
+    matches!(
+        e,
+        CouchbaseError::TemporaryFailure { .. }
+            | CouchbaseError::ServiceNotAvailable { .. }
+            | CouchbaseError::InternalServerFailure { .. }
+            | CouchbaseError::RequestCanceled { .. }
+            | CouchbaseError::AmbiguousTimeout { .. }
+            | CouchbaseError::UnambiguousTimeout { .. }
+            | CouchbaseError::BucketNotFound { .. }
+            | CouchbaseError::ScopeNotFound { .. }
+            | CouchbaseError::CollectionNotFound { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn is_busy(e: &&str) -> bool {
+        *e == "busy"
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transient_errors_are_retried_until_the_operation_succeeds() {
+        let attempts = Cell::new(0);
+
+        let result = retry_when(
+            || async {
+                attempts.set(attempts.get() + 1);
+                if attempts.get() < 3 {
+                    Err("busy")
+                } else {
+                    Ok(attempts.get())
+                }
+            },
+            is_busy,
+        )
+        .await;
+
+        assert_eq!(Ok(3), result);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transient_errors_are_returned_after_the_last_attempt() {
+        let attempts = Cell::new(0);
+
+        let result: Result<(), _> = retry_when(
+            || async {
+                attempts.set(attempts.get() + 1);
+                Err("busy")
+            },
+            is_busy,
+        )
+        .await;
+
+        assert_eq!(Err("busy"), result);
+        assert_eq!(RETRY_ATTEMPTS, attempts.get());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn other_errors_are_returned_right_away() {
+        let attempts = Cell::new(0);
+
+        let result: Result<(), _> = retry_when(
+            || async {
+                attempts.set(attempts.get() + 1);
+                Err("authentication failure")
+            },
+            is_busy,
+        )
+        .await;
+
+        assert_eq!(Err("authentication failure"), result);
+        assert_eq!(1, attempts.get());
+    }
+}